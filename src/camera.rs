@@ -1,28 +1,145 @@
 use bevy::app::{App, Plugin, PostUpdate, Startup};
-use bevy::math::{Vec3};
+use bevy::math::{Quat, Vec3};
 use bevy::pbr::{FogFalloff, FogSettings};
-use bevy::prelude::{Camera3dBundle, Color, Commands, Component, default, IntoSystemConfigs, Query, Reflect, Transform, With, Without};
+use bevy::prelude::{Camera3dBundle, Color, Commands, Component, default, Entity, Event, EventReader, GlobalTransform, IntoSystemConfigs, PerspectiveProjection, Projection, Query, Res, Time, Transform, With, Without};
 use bevy::transform::TransformSystem;
-use bevy_xpbd_3d::math::{Vector3};
 use bevy_xpbd_3d::PhysicsSet;
-use crate::santa::Santa;
+use bevy_xpbd_3d::prelude::{Collider, LinearVelocity, SpatialQuery, SpatialQueryFilter};
+use crate::collisions::SpawnExplosionAt;
+use crate::constants::{CAMERA_BLEND_TIME, CAMERA_COLLISION_RADIUS, CAMERA_ORBIT_SPEED, CAMERA_SHAKE_MAX_OFFSET, CAMERA_SHAKE_RADIUS, CAMERA_TRAUMA_DECAY};
+use crate::input::{ControlCommands, Controller};
+use crate::santa::{CollisionLayer, Santa};
+use crate::settings::Settings;
+use crate::terrain::Terrain;
+use crate::villages::VillageCenter;
 
 pub struct CameraPlugin;
 
 #[derive(Component)]
 pub struct GameCamera {}
 
-#[derive(Component, Reflect)]
-pub struct CameraOffset(pub Vec3);
+/// Adds this much trauma to the camera, 1.0 is as shaken as it gets
+#[derive(Event)]
+pub struct ShakeCamera(pub f32);
+
+/// The ways of looking at Santa, `V` cycles through them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CameraMode {
+    Chase,
+    Cockpit,
+    /// High above the closest village, for planning the deliveries
+    Tactical,
+    Orbit,
+}
+
+impl CameraMode {
+    pub fn next(&self) -> Self {
+        match self {
+            CameraMode::Chase => CameraMode::Cockpit,
+            CameraMode::Cockpit => CameraMode::Tactical,
+            CameraMode::Tactical => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Chase,
+        }
+    }
+
+    pub fn settings(&self) -> CameraModeSettings {
+        match self {
+            CameraMode::Chase => CameraModeSettings {
+                offset: Vec3::new(0.0, 10.0, -25.0),
+                stiffness: 8.0,
+                fov: 45.0_f32.to_radians(),
+                look_ahead: 0.3,
+            },
+            CameraMode::Cockpit => CameraModeSettings {
+                offset: Vec3::new(0.0, 2.0, 1.0),
+                stiffness: 30.0,
+                fov: 70.0_f32.to_radians(),
+                look_ahead: 1.0,
+            },
+            CameraMode::Tactical => CameraModeSettings {
+                offset: Vec3::new(0.0, 250.0, -60.0),
+                stiffness: 2.0,
+                fov: 50.0_f32.to_radians(),
+                look_ahead: 0.0,
+            },
+            CameraMode::Orbit => CameraModeSettings {
+                offset: Vec3::new(0.0, 12.0, -40.0),
+                stiffness: 4.0,
+                fov: 40.0_f32.to_radians(),
+                look_ahead: 0.5,
+            },
+        }
+    }
+}
+
+pub struct CameraModeSettings {
+    /// Where the camera sits, in Santa's frame for chase and cockpit
+    pub offset: Vec3,
+    /// Stiffness of the spring pulling the camera along, higher follows tighter
+    pub stiffness: f32,
+    pub fov: f32,
+    /// Seconds of Santa's velocity to look ahead of him
+    pub look_ahead: f32,
+}
+
+/// Where a mode wants the camera to be this frame
+#[derive(Clone, Copy)]
+pub struct CameraPose {
+    pub eye: Vec3,
+    pub target: Vec3,
+    pub fov: f32,
+}
+
+impl CameraPose {
+    pub fn blend(&self, other: &CameraPose, t: f32) -> CameraPose {
+        CameraPose {
+            eye: self.eye.lerp(other.eye, t),
+            target: self.target.lerp(other.target, t),
+            fov: self.fov + (other.fov - self.fov) * t,
+        }
+    }
+}
 
 #[derive(Component)]
-pub struct FollowCamera {
-    pub offset: Vector3,
+pub struct CameraRig {
+    pub mode: CameraMode,
+    /// The mode we are blending away from
+    pub previous_mode: CameraMode,
+    /// 0.0 right after switching, 1.0 when fully in the new mode
+    pub blend: f32,
+    pub orbit_angle: f32,
+    /// Spring velocity, the camera keeps moving a little when Santa stops
+    pub velocity: Vec3,
+    /// 0.0 to 1.0, how shaken up the camera is
+    pub trauma: f32,
+    /// The shake applied last frame, taken off again before the spring runs
+    pub shake: Vec3,
+}
+
+impl CameraRig {
+    pub fn new(mode: CameraMode) -> Self {
+        Self {
+            mode,
+            previous_mode: mode,
+            blend: 1.0,
+            orbit_angle: 0.0,
+            velocity: Vec3::ZERO,
+            trauma: 0.0,
+            shake: Vec3::ZERO,
+        }
+    }
+
+    pub fn switch_to(&mut self, mode: CameraMode) {
+        self.previous_mode = self.mode;
+        self.mode = mode;
+        self.blend = 0.0;
+    }
 }
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_event::<ShakeCamera>()
             .add_systems(
                 Startup, (
                     spawn_camera,
@@ -30,10 +147,13 @@ impl Plugin for CameraPlugin {
             .add_systems(
                 PostUpdate,
                 (
-                    camera_follow
-                        .after(PhysicsSet::Sync)
-                        .before(TransformSystem::TransformPropagate),
-                ),
+                    switch_camera_mode,
+                    add_camera_trauma,
+                    camera_follow,
+                )
+                    .chain()
+                    .after(PhysicsSet::Sync)
+                    .before(TransformSystem::TransformPropagate),
             );
     }
 }
@@ -48,22 +168,194 @@ fn spawn_camera(mut commands: Commands) {
         },
         // AtmosphereCamera::default(),
         GameCamera {},
-        CameraOffset(Vec3::new(0.0, 10.0, -25.0)),
+        CameraRig::new(CameraMode::Chase),
     ));
 }
 
-pub fn camera_follow(
-    mut camera_query: Query<(&mut Transform, &CameraOffset), (With<GameCamera>, Without<Santa>)>,
-    player_position: Query<&Transform, (With<Santa>, Without<GameCamera>)>,
+fn switch_camera_mode(
+    mut santa_query: Query<&mut Controller, With<Santa>>,
+    mut camera_query: Query<&mut CameraRig, With<GameCamera>>,
 ) {
-    for (mut camera_transform, offset) in camera_query.iter_mut() {
-        for player_position in player_position.iter() {
+    for mut controller in santa_query.iter_mut() {
+        if controller.triggers.remove(&ControlCommands::SwitchCamera) {
+            for mut camera_rig in camera_query.iter_mut() {
+                let next = camera_rig.mode.next();
+                camera_rig.switch_to(next);
+            }
+        }
+    }
+}
+
+/// Where `mode` would put the camera, looking at Santa or the village below him
+pub fn camera_pose(mode: CameraMode, santa: &Transform, velocity: Vec3, village: Option<Vec3>, orbit_angle: f32) -> CameraPose {
+    let settings = mode.settings();
+    let look_ahead = velocity * settings.look_ahead;
+    match mode {
+        CameraMode::Chase => CameraPose {
             //rotate the offset so it is BEHIND the player
-            let mut actual_offset = offset.0;
-            actual_offset = player_position.rotation.mul_vec3(actual_offset);
+            eye: santa.translation + santa.rotation.mul_vec3(settings.offset),
+            target: santa.translation + look_ahead,
+            fov: settings.fov,
+        },
+        CameraMode::Cockpit => {
+            let eye = santa.translation + santa.rotation.mul_vec3(settings.offset);
+            CameraPose {
+                eye,
+                target: eye + santa.rotation.mul_vec3(Vec3::Z) * 50.0 + look_ahead,
+                fov: settings.fov,
+            }
+        }
+        CameraMode::Tactical => {
+            let center = village.unwrap_or(santa.translation);
+            CameraPose {
+                eye: center + settings.offset,
+                target: center,
+                fov: settings.fov,
+            }
+        }
+        CameraMode::Orbit => CameraPose {
+            eye: santa.translation + Quat::from_rotation_y(orbit_angle).mul_vec3(settings.offset),
+            target: santa.translation + look_ahead,
+            fov: settings.fov,
+        },
+    }
+}
+
+/// Critically damped spring, exact for any delta so the lag doesn't change with the frame rate.
+/// Returns the new position and velocity.
+pub fn spring_damper(position: Vec3, velocity: Vec3, target: Vec3, stiffness: f32, delta: f32) -> (Vec3, Vec3) {
+    let decay = (-stiffness * delta).exp();
+    let change = position - target;
+    let temp = (velocity + change * stiffness) * delta;
+    let velocity = (velocity - temp * stiffness) * decay;
+    let position = target + (change + temp) * decay;
+    (position, velocity)
+}
+
+/// Wobble for the current trauma, squared so small knocks barely register
+pub fn shake_offset(trauma: f32, elapsed: f32) -> Vec3 {
+    let shake = trauma * trauma * CAMERA_SHAKE_MAX_OFFSET;
+    Vec3::new(
+        (elapsed * 37.0).sin(),
+        (elapsed * 43.0 + 1.0).sin(),
+        (elapsed * 29.0 + 2.0).sin(),
+    ) * shake
+}
+
+/// Explosions close to Santa and missiles hitting him shake the camera
+fn add_camera_trauma(
+    mut explosion_reader: EventReader<SpawnExplosionAt>,
+    mut shake_reader: EventReader<ShakeCamera>,
+    mut camera_query: Query<&mut CameraRig, With<GameCamera>>,
+    santa_query: Query<&Transform, With<Santa>>,
+    settings: Res<Settings>,
+) {
+    let mut trauma = 0.0;
+    if let Ok(santa_transform) = santa_query.get_single() {
+        for explosion in explosion_reader.read() {
+            let distance = explosion.position.distance(santa_transform.translation);
+            trauma += (1.0 - distance / CAMERA_SHAKE_RADIUS).max(0.0) * 0.5;
+        }
+    }
+    for shake in shake_reader.read() {
+        trauma += shake.0;
+    }
+    trauma *= settings.screen_shake;
+    if trauma <= 0.0 {
+        return;
+    }
+    for mut camera_rig in camera_query.iter_mut() {
+        camera_rig.trauma = (camera_rig.trauma + trauma).min(1.0);
+    }
+}
+
+pub fn camera_follow(
+    mut camera_query: Query<(&mut Transform, &mut Projection, &mut CameraRig), (With<GameCamera>, Without<Santa>)>,
+    player_position: Query<(Entity, &Transform, Option<&LinearVelocity>), (With<Santa>, Without<GameCamera>)>,
+    village_query: Query<&GlobalTransform, With<VillageCenter>>,
+    spatial_query: SpatialQuery,
+    terrain: Res<Terrain>,
+    time: Res<Time>,
+    settings: Res<Settings>,
+) {
+    let delta = time.delta_seconds();
+    for (mut camera_transform, mut projection, mut camera_rig) in camera_query.iter_mut() {
+        for (santa_entity, player_position, linear_velocity) in player_position.iter() {
+            let velocity = linear_velocity.map_or(Vec3::ZERO, |velocity| velocity.0);
+            let village = village_query
+                .iter()
+                .map(|village| village.translation())
+                .min_by(|a, b| a.distance_squared(player_position.translation).total_cmp(&b.distance_squared(player_position.translation)));
+
+            camera_rig.orbit_angle += CAMERA_ORBIT_SPEED * settings.camera_sensitivity * delta;
+            camera_rig.blend = (camera_rig.blend + delta / CAMERA_BLEND_TIME).min(1.0);
+            let from = camera_pose(camera_rig.previous_mode, player_position, velocity, village, camera_rig.orbit_angle);
+            let to = camera_pose(camera_rig.mode, player_position, velocity, village, camera_rig.orbit_angle);
+            // Smoothstep so the blend eases in and out
+            let t = camera_rig.blend * camera_rig.blend * (3.0 - 2.0 * camera_rig.blend);
+            let mut pose = from.blend(&to, t);
+
+            // Pull the camera in front of whatever is between it and what it looks at
+            let to_eye = pose.eye - pose.target;
+            let distance = to_eye.length();
+            if distance > f32::EPSILON {
+                let direction = to_eye / distance;
+                if let Some(hit) = spatial_query.cast_shape(
+                    &Collider::ball(CAMERA_COLLISION_RADIUS),
+                    pose.target,
+                    Quat::IDENTITY,
+                    direction,
+                    distance,
+                    true,
+                    SpatialQueryFilter::new()
+                        .with_masks([CollisionLayer::Ground, CollisionLayer::House, CollisionLayer::Solid])
+                        .without_entities([santa_entity]),
+                ) {
+                    pose.eye = pose.target + direction * hit.time_of_impact;
+                }
+            }
+            let ground = terrain.height_at(pose.eye.x, pose.eye.z) + CAMERA_COLLISION_RADIUS * 2.0;
+            pose.eye.y = pose.eye.y.max(ground);
+
+            // The spring works on the steady position, the shake goes on top
+            let steady_position = camera_transform.translation - camera_rig.shake;
+            let (position, spring_velocity) = spring_damper(steady_position, camera_rig.velocity, pose.eye, camera_rig.mode.settings().stiffness, delta);
+            camera_rig.velocity = spring_velocity;
+            camera_rig.trauma = (camera_rig.trauma - CAMERA_TRAUMA_DECAY * delta).max(0.0);
+            camera_rig.shake = shake_offset(camera_rig.trauma, time.elapsed_seconds());
+
+            camera_transform.translation = position + camera_rig.shake;
+            camera_transform.look_at(pose.target + camera_rig.shake * 0.5, Vec3::Y);
+            if let Projection::Perspective(PerspectiveProjection { fov, .. }) = projection.as_mut() {
+                if *fov != pose.fov {
+                    *fov = pose.fov;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 0.0001;
 
-            camera_transform.translation = camera_transform.translation.lerp(player_position.translation + actual_offset, 0.9);
-            camera_transform.look_at(player_position.translation, Vec3::Y);
+    #[test]
+    fn spring_damper_does_not_depend_on_the_frame_rate() {
+        let target = Vec3::new(3.0, -1.0, 2.0);
+        for (position, velocity) in [(Vec3::ZERO, Vec3::ZERO), (Vec3::new(-5.0, 2.0, 0.5), Vec3::new(1.0, 4.0, -2.0))] {
+            for stiffness in [1.0, 4.0, 12.0] {
+                for delta in [1.0 / 144.0, 1.0 / 60.0, 1.0 / 20.0] {
+                    let (one_position, one_velocity) = spring_damper(position, velocity, target, stiffness, 2.0 * delta);
+                    let (half_position, half_velocity) = spring_damper(position, velocity, target, stiffness, delta);
+                    let (two_position, two_velocity) = spring_damper(half_position, half_velocity, target, stiffness, delta);
+                    assert!(one_position.distance(two_position) < TOLERANCE,
+                            "stiffness {} delta {}: {} vs {}", stiffness, delta, one_position, two_position);
+                    assert!(one_velocity.distance(two_velocity) < TOLERANCE,
+                            "stiffness {} delta {}: {} vs {}", stiffness, delta, one_velocity, two_velocity);
+                }
+            }
         }
     }
 }
@@ -0,0 +1,184 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::prelude::{EventReader, EventWriter, Query, Res, ResMut, Resource, Time, With};
+use time::Duration;
+use crate::constants::{DAWN_HOUR, DUSK_HOUR, LATE_DELIVERY_FACTOR, NIGHT_LENGTH_SECONDS};
+use crate::santa::{GameEvent, GameEventTypes, Santa, SantaStats};
+use crate::ui::SillyGameState;
+use crate::villages::{LevelDefinition, LoadLevel};
+
+pub struct ChristmasEvePlugin;
+
+impl Plugin for ChristmasEvePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ChristmasEveClock::new(NIGHT_LENGTH_SECONDS))
+            .add_systems(
+                Update, (
+                    tick_clock,
+                    set_level_deadline,
+                    update_clock_stats,
+                ))
+        ;
+    }
+}
+
+/// Santa has one night to deliver everything, from dusk until dawn.
+#[derive(Resource)]
+pub struct ChristmasEveClock {
+    /// Game seconds since dusk
+    pub elapsed: f32,
+    /// How many game seconds the whole night lasts
+    pub night_length: f32,
+    /// When the current level has to be delivered, in game seconds since dusk
+    pub level_deadline: f32,
+    pub night_is_over: bool,
+}
+
+impl ChristmasEveClock {
+    pub fn new(night_length: f32) -> Self {
+        Self {
+            elapsed: 0.0,
+            night_length,
+            level_deadline: night_length,
+            night_is_over: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.night_length);
+    }
+
+    /// 0 at dusk, 1 at dawn
+    pub fn night_progress(&self) -> f32 {
+        (self.elapsed / self.night_length).clamp(0.0, 1.0)
+    }
+
+    pub fn time_of_day(&self) -> time::Time {
+        let dusk = time::Time::from_hms(DUSK_HOUR, 0, 0).unwrap();
+        let night_hours = (24 - DUSK_HOUR + DAWN_HOUR) as f32;
+        dusk + Duration::seconds_f32(self.night_progress() * night_hours * 3600.0)
+    }
+
+    pub fn level_time_left(&self) -> f32 {
+        (self.level_deadline - self.elapsed).max(0.0)
+    }
+
+    pub fn is_late(&self) -> bool {
+        self.elapsed > self.level_deadline
+    }
+
+    /// Late presents are still presents, just not as good
+    pub fn delivery_score(&self, score: u32) -> u32 {
+        if self.is_late() {
+            (score as f32 * LATE_DELIVERY_FACTOR) as u32
+        } else {
+            score
+        }
+    }
+}
+
+fn tick_clock(
+    mut clock: ResMut<ChristmasEveClock>,
+    time: Res<Time>,
+    silly_game_state: Res<SillyGameState>,
+    mut game_event_er: EventReader<GameEvent>,
+    mut game_event_ew: EventWriter<GameEvent>,
+) {
+    for game_event in game_event_er.read() {
+        if let GameEventTypes::Started | GameEventTypes::Restarted = game_event.event_type {
+            clock.reset();
+        }
+    }
+    if silly_game_state.waiting_for_restart || clock.night_is_over {
+        return;
+    }
+    clock.elapsed += time.delta_seconds();
+    if clock.elapsed >= clock.night_length {
+        clock.night_is_over = true;
+        game_event_ew.send(GameEvent { event_type: GameEventTypes::OutOfTime });
+    }
+}
+
+fn set_level_deadline(
+    mut clock: ResMut<ChristmasEveClock>,
+    mut load_level_er: EventReader<LoadLevel>,
+) {
+    for load_level in load_level_er.read() {
+        let level_definition = LevelDefinition::for_level(load_level.0);
+        clock.level_deadline = (clock.elapsed + level_definition.time_limit).min(clock.night_length);
+    }
+}
+
+fn update_clock_stats(
+    clock: Res<ChristmasEveClock>,
+    mut santa_query: Query<&mut SantaStats, With<Santa>>,
+) {
+    let time_of_day = clock.time_of_day();
+    let clock_label = format!("{:02}:{:02}", time_of_day.hour(), time_of_day.minute());
+    let time_left = clock.level_time_left() as u32;
+    let time_left_label = if clock.is_late() {
+        "LATE!".to_string()
+    } else {
+        format!("{}:{:02}", time_left / 60, time_left % 60)
+    };
+    for mut santa_stats in santa_query.iter_mut() {
+        // Only touch the stats when the text changes so the HUD doesn't rebuild every frame
+        if santa_stats.clock != clock_label {
+            santa_stats.clock = clock_label.clone();
+        }
+        if santa_stats.level_time_left != time_left_label {
+            santa_stats.level_time_left = time_left_label.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock_at(elapsed: f32, level_deadline: f32) -> ChristmasEveClock {
+        ChristmasEveClock {
+            elapsed,
+            level_deadline,
+            ..ChristmasEveClock::new(NIGHT_LENGTH_SECONDS)
+        }
+    }
+
+    #[test]
+    fn the_night_runs_from_dusk_to_dawn() {
+        let quarter = NIGHT_LENGTH_SECONDS / 4.0;
+        let night_hours = 24 - DUSK_HOUR + DAWN_HOUR;
+        let cases = [
+            (0.0, (DUSK_HOUR, 0)),
+            (quarter, ((DUSK_HOUR + night_hours / 4) % 24, (night_hours % 4) * 15)),
+            (2.0 * quarter, ((DUSK_HOUR + night_hours / 2) % 24, 0)),
+            (NIGHT_LENGTH_SECONDS, (DAWN_HOUR, 0)),
+            // The clock stops at dawn
+            (2.0 * NIGHT_LENGTH_SECONDS, (DAWN_HOUR, 0)),
+        ];
+        for (elapsed, (hour, minute)) in cases {
+            let time_of_day = clock_at(elapsed, NIGHT_LENGTH_SECONDS).time_of_day();
+            assert_eq!((time_of_day.hour(), time_of_day.minute()), (hour, minute), "{} seconds in", elapsed);
+        }
+    }
+
+    #[test]
+    fn time_left_runs_out_at_the_deadline() {
+        assert_eq!(clock_at(40.0, 100.0).level_time_left(), 60.0);
+        assert_eq!(clock_at(100.0, 100.0).level_time_left(), 0.0);
+        assert_eq!(clock_at(130.0, 100.0).level_time_left(), 0.0);
+    }
+
+    #[test]
+    fn late_deliveries_score_less() {
+        let full = 300;
+        let late = (full as f32 * LATE_DELIVERY_FACTOR) as u32;
+        // On time, exactly on the deadline still counts, just after it doesn't
+        let cases = [(40.0, full, false), (100.0, full, false), (100.01, late, true), (500.0, late, true)];
+        for (elapsed, score, is_late) in cases {
+            let clock = clock_at(elapsed, 100.0);
+            assert_eq!(clock.is_late(), is_late, "{} seconds in", elapsed);
+            assert_eq!(clock.delivery_score(full), score, "{} seconds in", elapsed);
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use std::f32::consts::PI;
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::asset::{Assets, AssetServer, Handle};
+use bevy::core::Name;
+use bevy::hierarchy::{BuildChildren, ChildBuilder, Children, DespawnRecursiveExt, HierarchyQueryExt};
+use bevy::math::{IVec2, Quat, Vec3};
+use bevy::pbr::{PbrBundle, StandardMaterial};
+use bevy::prelude::{Color, Commands, Entity, GlobalTransform, Mesh, Query, Res, ResMut, Resource, Scene, shape, Transform, With};
+use bevy::scene::SceneBundle;
+use bevy::utils::{default, HashMap};
+use bevy_turborand::{DelegatedRng, RngComponent};
+use crate::constants::{CHUNKS_SPAWNED_PER_FRAME, CHUNK_UNLOAD_RADIUS, CHUNK_VIEW_RADIUS, MAX_LIVE_CHUNK_ENTITIES, TERRAIN_CHUNK_SIZE};
+use crate::santa::Santa;
+use crate::terrain::{spawn_terrain_chunk, FlatSite, Terrain, TerrainAssets};
+
+pub struct ChunkPlugin;
+
+impl Plugin for ChunkPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ChunkManager::new(CHUNK_VIEW_RADIUS, CHUNK_UNLOAD_RADIUS, MAX_LIVE_CHUNK_ENTITIES))
+            .init_resource::<ChunkAssets>()
+            .add_systems(Startup, load_chunk_assets)
+            .add_systems(Update, stream_chunks)
+        ;
+    }
+}
+
+pub struct LoadedChunk {
+    pub entity: Entity,
+    /// The terrain itself plus everything on it, trees included with every entity
+    /// their scene spawned. Recounted every frame as the scenes come in.
+    pub entity_count: usize,
+}
+
+/// Keeps track of which chunks of the world are alive around Santa.
+#[derive(Resource)]
+pub struct ChunkManager {
+    pub loaded: HashMap<IVec2, LoadedChunk>,
+    pub view_radius: i32,
+    pub unload_radius: i32,
+    pub max_live_entities: usize,
+    /// The flat sites the loaded chunks were built with
+    pub flat_sites: Vec<FlatSite>,
+}
+
+impl ChunkManager {
+    pub fn new(view_radius: i32, unload_radius: i32, max_live_entities: usize) -> Self {
+        Self {
+            loaded: HashMap::default(),
+            view_radius,
+            unload_radius,
+            max_live_entities,
+            flat_sites: Vec::new(),
+        }
+    }
+
+    pub fn chunk_at(position: Vec3) -> IVec2 {
+        IVec2::new(
+            (position.x / TERRAIN_CHUNK_SIZE).floor() as i32,
+            (position.z / TERRAIN_CHUNK_SIZE).floor() as i32)
+    }
+
+    pub fn live_entities(&self) -> usize {
+        self.loaded.values().map(|chunk| chunk.entity_count).sum()
+    }
+
+    /// Chunks within the view radius that aren't loaded yet, closest first
+    fn missing_chunks(&self, center: IVec2) -> Vec<IVec2> {
+        let mut missing: Vec<IVec2> = (-self.view_radius..=self.view_radius)
+            .flat_map(|x| (-self.view_radius..=self.view_radius).map(move |z| IVec2::new(x, z)))
+            .filter(|offset| offset.length_squared() <= self.view_radius * self.view_radius)
+            .map(|offset| center + offset)
+            .filter(|chunk| !self.loaded.contains_key(chunk))
+            .collect();
+        missing.sort_by_key(|chunk| (*chunk - center).length_squared());
+        missing
+    }
+
+    /// The chunk to drop to make room for `wanted`, never one closer to Santa than it
+    fn chunk_to_evict(&self, center: IVec2, wanted: IVec2) -> Option<IVec2> {
+        self.loaded
+            .keys()
+            .max_by_key(|chunk| (**chunk - center).length_squared())
+            .filter(|farthest| (**farthest - center).length_squared() > (wanted - center).length_squared())
+            .copied()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ChunkAssets {
+    pub pine_tree: Handle<Scene>,
+    pub rock_mesh: Handle<Mesh>,
+    pub rock_material: Handle<StandardMaterial>,
+    pub fence_post_mesh: Handle<Mesh>,
+    pub fence_rail_mesh: Handle<Mesh>,
+    pub fence_material: Handle<StandardMaterial>,
+    pub snowman_mesh: Handle<Mesh>,
+    pub snowman_material: Handle<StandardMaterial>,
+}
+
+pub fn load_chunk_assets(
+    asset_server: ResMut<AssetServer>,
+    mut chunk_assets: ResMut<ChunkAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    *chunk_assets = ChunkAssets {
+        pine_tree: asset_server.load("models/christmas-tree.glb#Scene0"),
+        rock_mesh: meshes.add(
+            shape::UVSphere {
+                radius: 1.0,
+                sectors: 6,
+                stacks: 4,
+            }.into()),
+        rock_material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.35, 0.35, 0.38),
+            perceptual_roughness: 1.0,
+            ..default()
+        }),
+        fence_post_mesh: meshes.add(Mesh::from(shape::Box::new(0.3, 2.0, 0.3))),
+        fence_rail_mesh: meshes.add(Mesh::from(shape::Box::new(0.15, 0.2, 1.0))),
+        fence_material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.35, 0.22, 0.12),
+            ..default()
+        }),
+        snowman_mesh: meshes.add(
+            shape::UVSphere {
+                radius: 1.0,
+                sectors: 12,
+                stacks: 8,
+            }.into()),
+        snowman_material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.95, 0.95, 1.0),
+            ..default()
+        }),
+    }
+}
+
+/// The same chunk always gets the same random numbers, so it looks the same when we come back
+fn chunk_rng(seed: u32, chunk: IVec2) -> RngComponent {
+    RngComponent::with_seed(
+        (seed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (chunk.x as i64 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+            ^ (chunk.y as i64 as u64).wrapping_mul(0x1656_67b1_9e37_79f9))
+}
+
+fn stream_chunks(
+    mut commands: Commands,
+    mut chunk_manager: ResMut<ChunkManager>,
+    santa_query: Query<&GlobalTransform, With<Santa>>,
+    children_query: Query<&Children>,
+    terrain: Res<Terrain>,
+    terrain_assets: Res<TerrainAssets>,
+    chunk_assets: Res<ChunkAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Ok(santa_transform) = santa_query.get_single() else {
+        return;
+    };
+    let center = ChunkManager::chunk_at(santa_transform.translation());
+
+    for loaded_chunk in chunk_manager.loaded.values_mut() {
+        let descendants = children_query.iter_descendants(loaded_chunk.entity).count();
+        // The children of a chunk spawned last frame might not be there yet, keep the estimate until they are
+        loaded_chunk.entity_count = loaded_chunk.entity_count.max(1 + descendants);
+    }
+
+    // A village levelled the ground (or went away), the chunks under it get built again
+    if terrain.flat_sites != chunk_manager.flat_sites {
+        let changed: Vec<FlatSite> = terrain.flat_sites
+            .iter()
+            .chain(chunk_manager.flat_sites.iter())
+            .filter(|site| !(terrain.flat_sites.contains(site) && chunk_manager.flat_sites.contains(site)))
+            .copied()
+            .collect();
+        let stale: Vec<IVec2> = chunk_manager.loaded
+            .keys()
+            .filter(|chunk| changed.iter().any(|site| site.overlaps_chunk(**chunk)))
+            .copied()
+            .collect();
+        for chunk in stale {
+            if let Some(loaded_chunk) = chunk_manager.loaded.remove(&chunk) {
+                commands.entity(loaded_chunk.entity).despawn_recursive();
+            }
+        }
+        chunk_manager.flat_sites = terrain.flat_sites.clone();
+    }
+
+    // Some slack between loading and unloading so flying along a chunk border doesn't thrash
+    let unload_radius_squared = chunk_manager.unload_radius * chunk_manager.unload_radius;
+    let out_of_range: Vec<IVec2> = chunk_manager.loaded
+        .keys()
+        .filter(|chunk| (**chunk - center).length_squared() > unload_radius_squared)
+        .copied()
+        .collect();
+    for chunk in out_of_range {
+        if let Some(loaded_chunk) = chunk_manager.loaded.remove(&chunk) {
+            commands.entity(loaded_chunk.entity).despawn_recursive();
+        }
+    }
+
+    // Closest first, so at the start or after Santa was moved somewhere new the ground
+    // right under him is the first thing to be there
+    for chunk in chunk_manager.missing_chunks(center).into_iter().take(CHUNKS_SPAWNED_PER_FRAME) {
+        // Make room by dropping chunks that are farther away than the one we want
+        while chunk_manager.live_entities() >= chunk_manager.max_live_entities {
+            let Some(farthest) = chunk_manager.chunk_to_evict(center, chunk) else {
+                return;
+            };
+            let loaded_chunk = chunk_manager.loaded.remove(&farthest).unwrap();
+            commands.entity(loaded_chunk.entity).despawn_recursive();
+        }
+
+        let entity = spawn_terrain_chunk(&mut commands, &terrain, &terrain_assets, &mut meshes, chunk);
+        let mut rng = chunk_rng(terrain.seed, chunk);
+        let mut scenery_count = 0;
+        commands.entity(entity).with_children(|children| {
+            scenery_count = spawn_scenery(children, &terrain, &chunk_assets, &mut rng, chunk);
+        });
+        chunk_manager.loaded.insert(chunk, LoadedChunk {
+            entity,
+            entity_count: 1 + scenery_count,
+        });
+    }
+}
+
+/// Trees, rocks, fences and snowmen, positioned relative to the chunk origin.
+/// Returns how many entities were spawned.
+fn spawn_scenery(
+    children: &mut ChildBuilder,
+    terrain: &Terrain,
+    chunk_assets: &ChunkAssets,
+    rng: &mut RngComponent,
+    chunk: IVec2,
+) -> usize {
+    let origin = Terrain::chunk_origin(chunk);
+    let mut count = 0;
+    let ground = |x: f32, z: f32| -> Option<Vec3> {
+        let (world_x, world_z) = (origin.x + x, origin.z + z);
+        // Nothing grows in lakes or in the middle of a village
+        if terrain.is_lake(world_x, world_z) || terrain.is_flat_site(world_x, world_z) {
+            None
+        } else {
+            Some(Vec3::new(x, terrain.height_at(world_x, world_z), z))
+        }
+    };
+
+    // Trees grow in small forests
+    let number_of_forests = rng.usize(0..3);
+    for _ in 0..number_of_forests {
+        let forest_x = rng.f32() * TERRAIN_CHUNK_SIZE;
+        let forest_z = rng.f32() * TERRAIN_CHUNK_SIZE;
+        for _ in 0..rng.usize(3..10) {
+            let x = (forest_x + rng.f32_normalized() * 30.0).clamp(0.0, TERRAIN_CHUNK_SIZE);
+            let z = (forest_z + rng.f32_normalized() * 30.0).clamp(0.0, TERRAIN_CHUNK_SIZE);
+            let scale = 0.4 + rng.f32() * 0.4;
+            let rotation = Quat::from_rotation_y(rng.f32() * 2.0 * PI);
+            if let Some(position) = ground(x, z) {
+                children.spawn((
+                    Name::from("Pine Tree"),
+                    SceneBundle {
+                        scene: chunk_assets.pine_tree.clone(),
+                        transform: Transform::from_translation(position)
+                            .with_rotation(rotation)
+                            .with_scale(Vec3::splat(scale)),
+                        ..default()
+                    },
+                ));
+                count += 1;
+            }
+        }
+    }
+
+    for _ in 0..rng.usize(0..6) {
+        let x = rng.f32() * TERRAIN_CHUNK_SIZE;
+        let z = rng.f32() * TERRAIN_CHUNK_SIZE;
+        let scale = Vec3::new(1.0 + rng.f32() * 2.0, 0.6 + rng.f32(), 1.0 + rng.f32() * 2.0);
+        let rotation = Quat::from_rotation_y(rng.f32() * 2.0 * PI);
+        if let Some(position) = ground(x, z) {
+            children.spawn((
+                Name::from("Rock"),
+                PbrBundle {
+                    mesh: chunk_assets.rock_mesh.clone(),
+                    material: chunk_assets.rock_material.clone(),
+                    transform: Transform::from_translation(position)
+                        .with_rotation(rotation)
+                        .with_scale(scale),
+                    ..default()
+                },
+            ));
+            count += 1;
+        }
+    }
+
+    if rng.f32() < 0.3 {
+        let start_x = rng.f32() * TERRAIN_CHUNK_SIZE;
+        let start_z = rng.f32() * TERRAIN_CHUNK_SIZE;
+        let direction = Quat::from_rotation_y(rng.f32() * 2.0 * PI).mul_vec3(Vec3::Z);
+        let post_spacing = 4.0;
+        let mut previous_post: Option<Vec3> = None;
+        for n in 0..rng.usize(6..12) {
+            let x = start_x + direction.x * post_spacing * n as f32;
+            let z = start_z + direction.z * post_spacing * n as f32;
+            let Some(post) = ground(x, z) else {
+                previous_post = None;
+                continue;
+            };
+            children.spawn((
+                Name::from("Fence Post"),
+                PbrBundle {
+                    mesh: chunk_assets.fence_post_mesh.clone(),
+                    material: chunk_assets.fence_material.clone(),
+                    transform: Transform::from_translation(post + Vec3::Y),
+                    ..default()
+                },
+            ));
+            count += 1;
+            if let Some(previous) = previous_post {
+                let rail_center = (previous + post) / 2.0 + Vec3::Y * 1.4;
+                children.spawn((
+                    Name::from("Fence Rail"),
+                    PbrBundle {
+                        mesh: chunk_assets.fence_rail_mesh.clone(),
+                        material: chunk_assets.fence_material.clone(),
+                        transform: Transform::from_translation(rail_center)
+                            .looking_at(post + Vec3::Y * 1.4, Vec3::Y)
+                            .with_scale(Vec3::new(1.0, 1.0, previous.distance(post))),
+                        ..default()
+                    },
+                ));
+                count += 1;
+            }
+            previous_post = Some(post);
+        }
+    }
+
+    if rng.f32() < 0.1 {
+        let x = rng.f32() * TERRAIN_CHUNK_SIZE;
+        let z = rng.f32() * TERRAIN_CHUNK_SIZE;
+        if let Some(position) = ground(x, z) {
+            // Three balls of snow stacked on top of each other, a classic
+            for (height, radius) in [(1.2, 1.2), (2.9, 0.85), (4.1, 0.55)] {
+                children.spawn((
+                    Name::from("Snowman"),
+                    PbrBundle {
+                        mesh: chunk_assets.snowman_mesh.clone(),
+                        material: chunk_assets.snowman_material.clone(),
+                        transform: Transform::from_translation(position + Vec3::Y * height)
+                            .with_scale(Vec3::splat(radius)),
+                        ..default()
+                    },
+                ));
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager_with(chunks: &[(IVec2, usize)]) -> ChunkManager {
+        let mut manager = ChunkManager::new(2, 3, 100);
+        for (index, (chunk, entity_count)) in chunks.iter().enumerate() {
+            manager.loaded.insert(*chunk, LoadedChunk {
+                entity: Entity::from_raw(index as u32),
+                entity_count: *entity_count,
+            });
+        }
+        manager
+    }
+
+    #[test]
+    fn chunk_at_rounds_down_on_both_sides_of_zero() {
+        let size = TERRAIN_CHUNK_SIZE;
+        let cases = [
+            (Vec3::new(0.0, 50.0, 0.0), IVec2::new(0, 0)),
+            (Vec3::new(size - 0.1, 0.0, size), IVec2::new(0, 1)),
+            (Vec3::new(-0.1, 0.0, -size), IVec2::new(-1, -1)),
+            (Vec3::new(-size - 0.1, 0.0, 2.5 * size), IVec2::new(-2, 2)),
+        ];
+        for (position, expected) in cases {
+            assert_eq!(ChunkManager::chunk_at(position), expected, "{}", position);
+        }
+    }
+
+    #[test]
+    fn missing_chunks_start_under_santa_and_stay_in_range() {
+        let center = IVec2::new(5, -3);
+        let manager = manager_with(&[(center + IVec2::X, 1)]);
+        let missing = manager.missing_chunks(center);
+        assert_eq!(missing.first(), Some(&center));
+        assert!(!missing.contains(&(center + IVec2::X)));
+        // A circle of radius 2 is 13 chunks, one of them is already there
+        assert_eq!(missing.len(), 12);
+        for pair in missing.windows(2) {
+            assert!((pair[0] - center).length_squared() <= (pair[1] - center).length_squared(), "{:?}", missing);
+        }
+        assert!(missing.iter().all(|chunk| (*chunk - center).length_squared() <= 4));
+    }
+
+    #[test]
+    fn only_evicts_chunks_farther_away_than_the_one_wanted() {
+        let center = IVec2::ZERO;
+        let manager = manager_with(&[(IVec2::new(1, 0), 40), (IVec2::new(3, 0), 40), (IVec2::new(0, -2), 40)]);
+        assert_eq!(manager.live_entities(), 120);
+        assert_eq!(manager.chunk_to_evict(center, IVec2::new(0, 1)), Some(IVec2::new(3, 0)));
+        assert_eq!(manager.chunk_to_evict(center, IVec2::new(2, 2)), Some(IVec2::new(3, 0)));
+        assert_eq!(manager.chunk_to_evict(center, IVec2::new(0, 3)), None);
+        assert_eq!(manager_with(&[]).chunk_to_evict(center, IVec2::ZERO), None);
+    }
+}
@@ -1,14 +1,19 @@
 use bevy::app::{App, Plugin, Update};
-use bevy::hierarchy::{BuildChildren, DespawnRecursiveExt};
+use bevy::hierarchy::DespawnRecursiveExt;
 use bevy::math::Vec3;
-use bevy::pbr::{PbrBundle, PointLight, PointLightBundle};
-use bevy::prelude::{Color, Commands, default, Entity, Event, EventReader, EventWriter, GlobalTransform, Query, Res, ResMut, Transform, With, Without};
+use bevy::prelude::{Commands, Entity, Event, EventReader, EventWriter, GlobalTransform, Or, Query, Res, ResMut, Time, Transform, With, Without};
+use bevy::utils::HashMap;
 use bevy_turborand::{DelegatedRng, GlobalRng};
 use bevy_xpbd_3d::prelude::CollisionStarted;
-use crate::assets::SantasAssets;
-use crate::sam_site::{MissileTrail, SamChild, SamSite, SurfaceToAirMissile};
-use crate::santa::{GiftChild, SantaStats, ParentEntity, Santa, SantaChild, TargetEvent, TargetEventTypes};
-use crate::villages::{House, HouseChild, HouseEvent, HouseEventType, LoadLevel, NeedsGifts, VillageCenter};
+use crate::camera::ShakeCamera;
+use crate::christmas_eve::ChristmasEveClock;
+use crate::constants::{COAL_SCORE, SANTA_FLIGHT_HEIGHT, SANTA_GROUND_CLEARANCE, SANTA_SETTLE_SPEED, TERRAIN_CRASH_DAMAGE, WRONG_DELIVERY_PENALTY, WRONG_DELIVERY_SPIRIT_LOSS};
+use crate::input::Controller;
+use crate::particles::{ParticleEffect, ParticleEmitter};
+use crate::sam_site::{SamChild, SamSite, SurfaceToAirMissile};
+use crate::santa::{GiftChild, SantaStats, ParentEntity, Payload, Santa, SantaChild, TargetEvent, TargetEventTypes};
+use crate::terrain::{Terrain, TerrainChunk};
+use crate::villages::{Disposition, GameTracker, GiftDemand, HitZone, HitZoneType, House, HouseChild, HouseEvent, HouseEventType, LoadLevel, NeedsCoal, NeedsGifts, VillageCenter};
 
 pub struct CollisionsPlugin;
 
@@ -17,10 +22,12 @@ impl Plugin for CollisionsPlugin {
         app
             .add_event::<SpawnExplosionAt>()
             .add_event::<LevelFinished>()
+            .add_event::<VillageFinished>()
             .add_systems(Update, (
                 missile_santa_collision_handler,
+                santa_terrain_collision_handler,
+                keep_santa_above_terrain,
                 gift_house_collision_handler,
-                spawn_explosions,
                 received_gifts_handler,
                 level_finished_handler,
             ))
@@ -42,6 +49,7 @@ fn missile_santa_collision_handler(
     santa_child_query: Query<&ParentEntity, (With<SantaChild>, Without<SamChild>)>,
     missile_child_query: Query<&ParentEntity, (With<SamChild>, Without<SantaChild>)>,
     mut global_rng: ResMut<GlobalRng>,
+    mut shake_ew: EventWriter<ShakeCamera>,
 ) {
     for collision in collision_reader.read() {
         if missile_child_query.contains(collision.0) || missile_child_query.contains(collision.1) {
@@ -61,68 +69,189 @@ fn missile_santa_collision_handler(
 
                 if let Ok(mut santa_health) = santa_query.get_mut(santa_entity) {
                     santa_health.health -= global_rng.i32(5..=15);
+                    shake_ew.send(ShakeCamera(0.6));
                 }
             }
         }
     }
 }
 
+fn santa_terrain_collision_handler(
+    mut collision_reader: EventReader<CollisionStarted>,
+    mut explosion_ew: EventWriter<SpawnExplosionAt>,
+    mut commands: Commands,
+    mut santa_query: Query<(&mut SantaStats, &Transform, &mut Controller), With<Santa>>,
+    santa_child_query: Query<&ParentEntity, With<SantaChild>>,
+    terrain_query: Query<(), With<TerrainChunk>>,
+    mut shake_ew: EventWriter<ShakeCamera>,
+) {
+    for collision in collision_reader.read() {
+        let santa_child = if santa_child_query.contains(collision.0) && terrain_query.contains(collision.1) {
+            collision.0
+        } else if santa_child_query.contains(collision.1) && terrain_query.contains(collision.0) {
+            collision.1
+        } else {
+            continue;
+        };
+        let santa_entity = santa_child_query.get(santa_child).unwrap().0;
+        if let Ok((mut santa_stats, transform, mut controller)) = santa_query.get_mut(santa_entity) {
+            santa_stats.health -= TERRAIN_CRASH_DAMAGE;
+            shake_ew.send(ShakeCamera(0.4));
+            explosion_ew.send(SpawnExplosionAt {
+                position: transform.translation,
+            });
+            commands.spawn(ParticleEmitter::burst_at(ParticleEffect::SnowImpact, transform.translation));
+            controller.speed *= 0.5;
+        }
+    }
+}
+
+/// Santa never ends up inside a hill he crashed into. He is lifted onto the ground and
+/// shoved downhill a bit, then sinks back to his flight height once he is clear.
+fn keep_santa_above_terrain(
+    mut santa_query: Query<&mut Transform, With<Santa>>,
+    terrain: Res<Terrain>,
+    time: Res<Time>,
+) {
+    for mut transform in santa_query.iter_mut() {
+        let (x, z) = (transform.translation.x, transform.translation.z);
+        let lowest = terrain.height_at(x, z) + SANTA_GROUND_CLEARANCE;
+        let penetration = lowest - transform.translation.y;
+        if penetration > 0.0 {
+            transform.translation.y = lowest;
+            transform.translation += terrain.downhill_at(x, z) * penetration;
+        } else if transform.translation.y > SANTA_FLIGHT_HEIGHT {
+            let settled = transform.translation.y - SANTA_SETTLE_SPEED * time.delta_seconds();
+            transform.translation.y = settled.max(SANTA_FLIGHT_HEIGHT).max(lowest);
+        }
+    }
+}
+
 fn gift_house_collision_handler(
     mut collision_reader: EventReader<CollisionStarted>,
     mut explosion_ew: EventWriter<SpawnExplosionAt>,
     mut commands: Commands,
-    missile_query: Query<(&SurfaceToAirMissile, &GlobalTransform)>,
+    missile_query: Query<(&Payload, &GlobalTransform), With<SurfaceToAirMissile>>,
     missile_child_query: Query<&ParentEntity, (With<GiftChild>, Without<HouseChild>)>,
-    house_child_query: Query<&ParentEntity, (With<HouseChild>, Without<GiftChild>)>,
+    house_child_query: Query<(&ParentEntity, &HitZone, &GlobalTransform), (With<HouseChild>, Without<GiftChild>)>,
     mut house_ew: EventWriter<HouseEvent>,
 ) {
+    // A gift can touch the roof and the chimney in the same frame, only the best hit counts
+    let mut best_hits: HashMap<Entity, Entity> = HashMap::default();
     for collision in collision_reader.read() {
-        if missile_child_query.contains(collision.0) || missile_child_query.contains(collision.1) {
-            if house_child_query.contains(collision.0) || house_child_query.contains(collision.1) {
-                let (house_child_entity, missile_entity) = if house_child_query.contains(collision.0) {
-                    (house_child_query.get(collision.0).unwrap().0, missile_child_query.get(collision.1).unwrap().0)
-                } else {
-                    (house_child_query.get(collision.1).unwrap().0, missile_child_query.get(collision.0).unwrap().0)
-                };
-                if let Ok((_, missile_transform)) = missile_query.get(missile_entity) {
-                    explosion_ew.send(SpawnExplosionAt {
-                        position: missile_transform.translation(),
-                    });
-                }
-                commands.entity(missile_entity).despawn_recursive();
+        let (house_child, missile_child) = if house_child_query.contains(collision.0) && missile_child_query.contains(collision.1) {
+            (collision.0, collision.1)
+        } else if house_child_query.contains(collision.1) && missile_child_query.contains(collision.0) {
+            (collision.1, collision.0)
+        } else {
+            continue;
+        };
+        let missile_entity = missile_child_query.get(missile_child).unwrap().0;
+        let (_, hit_zone, _) = house_child_query.get(house_child).unwrap();
+        let is_better = best_hits
+            .get(&missile_entity)
+            .and_then(|best_child| house_child_query.get(*best_child).ok())
+            .map_or(true, |(_, best_zone, _)| hit_zone.zone_type.precision() > best_zone.zone_type.precision());
+        if is_better {
+            best_hits.insert(missile_entity, house_child);
+        }
+    }
 
-                house_ew.send(HouseEvent(HouseEventType::ReceivedGifts(house_child_entity)));
-            }
+    for (missile_entity, house_child) in best_hits {
+        let (house_entity, hit_zone, zone_transform) = house_child_query.get(house_child).unwrap();
+        let Ok((payload, missile_transform)) = missile_query.get(missile_entity) else {
+            continue;
+        };
+        let impact_point = hit_zone.closest_point(zone_transform, missile_transform.translation());
+        explosion_ew.send(SpawnExplosionAt {
+            position: impact_point,
+        });
+        if hit_zone.zone_type == HitZoneType::Roof {
+            // Knocks the snow off the roof
+            commands.spawn(ParticleEmitter::burst_at(ParticleEffect::SnowImpact, impact_point));
         }
+        commands.entity(missile_entity).despawn_recursive();
+
+        house_ew.send(HouseEvent(HouseEventType::ReceivedGifts {
+            house: house_entity.0,
+            gift: missile_entity,
+            payload: *payload,
+            zone: hit_zone.zone_type,
+            impact_point,
+        }));
     }
 }
 
 #[derive(Event)]
 pub struct LevelFinished(pub u32);
 
+#[derive(Event)]
+pub struct VillageFinished(pub Entity);
+
 fn received_gifts_handler(
     mut gifts_received_er: EventReader<HouseEvent>,
-    house_query: Query<&House>,
-    mut village_center_query: Query<&mut VillageCenter>,
+    mut house_query: Query<(&House, &Disposition, Option<&mut GiftDemand>), Or<(With<NeedsGifts>, With<NeedsCoal>)>>,
+    mut village_center_query: Query<(Entity, &mut VillageCenter)>,
+    mut santa_query: Query<&mut SantaStats, With<Santa>>,
     mut commands: Commands,
     mut level_finished_ew: EventWriter<LevelFinished>,
-    mut target_event_ew: EventWriter<TargetEvent>
+    mut village_finished_ew: EventWriter<VillageFinished>,
+    mut target_event_ew: EventWriter<TargetEvent>,
+    mut game_tracker: ResMut<GameTracker>,
+    clock: Res<ChristmasEveClock>,
 ) {
     for gifts_received in gifts_received_er.read() {
         match gifts_received.0 {
-            HouseEventType::ReceivedGifts(house_entity) => {
-                commands.entity(house_entity).remove::<NeedsGifts>();
+            HouseEventType::ReceivedGifts { house: house_entity, payload, zone, .. } => {
                 target_event_ew.send(TargetEvent(TargetEventTypes::StopShooting));
-                if let Ok(house) = house_query.get(house_entity) {
-                    if let Ok(mut village_center) = village_center_query.get_mut(house.belongs_to_village) {
-                        village_center.needs_gifts_count -= 1;
-                        if village_center.needs_gifts_count <= 0 && village_center.needs_gifts {
-                            village_center.needs_gifts = false;
-                            level_finished_ew.send(LevelFinished(village_center.level));
+                let Ok((house, disposition, gift_demand)) = house_query.get_mut(house_entity) else {
+                    continue;
+                };
+                match (disposition, payload) {
+                    (Disposition::Nice, Payload::Gift) => {}
+                    (Disposition::Naughty, Payload::Coal) => {
+                        game_tracker.score += clock.delivery_score(COAL_SCORE);
+                        commands.entity(house_entity).remove::<NeedsCoal>();
+                        continue;
+                    }
+                    (Disposition::Nice, Payload::Coal) | (Disposition::Naughty, Payload::Gift) => {
+                        // Presents for the naughty and coal for the nice, that's not the spirit
+                        game_tracker.score = game_tracker.score.saturating_sub(WRONG_DELIVERY_PENALTY);
+                        for mut santa_stats in santa_query.iter_mut() {
+                            santa_stats.christmas_spirit -= WRONG_DELIVERY_SPIRIT_LOSS;
                         }
+                        continue;
                     }
+                    (Disposition::Empty, _) => continue,
+                }
+                let Some(mut gift_demand) = gift_demand else {
+                    continue;
+                };
+                game_tracker.score += clock.delivery_score(zone.score());
+                gift_demand.received += 1;
+                if !gift_demand.is_met() {
+                    continue;
                 }
+                commands.entity(house_entity).remove::<NeedsGifts>();
+                if let Ok((village_entity, mut village_center)) = village_center_query.get_mut(house.belongs_to_village) {
+                    village_center.needs_gifts_count -= 1;
+                    if village_center.needs_gifts_count <= 0 && village_center.needs_gifts {
+                        village_center.needs_gifts = false;
+                        village_finished_ew.send(VillageFinished(village_entity));
 
+                        if village_center.is_bonus {
+                            continue;
+                        }
+                        let level = village_center.level;
+                        let level_is_finished = village_center_query
+                            .iter()
+                            .filter(|(_, village)| village.level == level && !village.is_bonus)
+                            .all(|(_, village)| !village.needs_gifts);
+                        if level_is_finished {
+                            level_finished_ew.send(LevelFinished(level));
+                        }
+                    }
+                }
             }
         }
     }
@@ -145,46 +274,3 @@ fn level_finished_handler(
         }
     }
 }
-
-fn spawn_explosions(
-    mut commands: Commands,
-    mut explosion_reader: EventReader<SpawnExplosionAt>,
-    mut global_rng: ResMut<GlobalRng>,
-    santas_assets: Res<SantasAssets>,
-) {
-    for explosion in explosion_reader.read() {
-        let explosion_size = global_rng.i32(3..=8);
-        for _i in 1..explosion_size {
-            let missile_trail = MissileTrail::new(0.5 * global_rng.f32(), global_rng.f32(), (global_rng.f32() + 0.5) * 10.0);
-            commands.spawn((
-                PbrBundle {
-                    mesh: santas_assets.trail_mesh.clone(),
-                    material: santas_assets.trail_material.clone(),
-                    transform: Transform::from_xyz(
-                        explosion.position.x + global_rng.f32_normalized() * 5.0,
-                        explosion.position.y + global_rng.f32_normalized() * 5.0,
-                        explosion.position.z + global_rng.f32_normalized() * 5.0,
-                    )
-                        .with_scale(Vec3::new(missile_trail.start_scale, missile_trail.start_scale, missile_trail.start_scale)),
-                    ..Default::default()
-                },
-                missile_trail
-            )).with_children(|children|
-                { // Spawn the child colliders positioned relative to the rigid body
-                    children.spawn((
-                        PointLightBundle {
-                            point_light: PointLight {
-                                color: Color::rgb(global_rng.f32(), global_rng.f32(), 0.0),
-                                intensity: (global_rng.f32() + 0.5) * 80000.0, // Roughly a 60W non-halogen incandescent bulb
-                                range: 40.0,
-                                radius: 0.0,
-                                shadows_enabled: true,
-                                ..default()
-                            },
-                            ..Default::default()
-                        },
-                    ));
-                });
-        }
-    }
-}
@@ -1,6 +1,16 @@
 pub const GROUND_PLANE: f32 = -15.0;
-pub const SNOW_CEILING: f32 = 20.0;
-pub const SNOW_SPAWN_SIZE: f32 = 50.0;
+pub const SNOW_POOL_SIZE: usize = 4000;
+pub const SNOW_BOX_HALF_SIZE: f32 = 60.0;
+pub const SNOW_BOX_HALF_HEIGHT: f32 = 30.0;
+pub const SNOW_FLAKE_SIZE: f32 = 0.05;
+pub const PARTICLE_COLOR_STEPS: usize = 8;
+pub const PARTICLE_POOL_SIZE: usize = 500;
+pub const MAX_DYNAMIC_LIGHTS: usize = 16;
+pub const MAX_SHADOWED_LIGHTS: usize = 4;
+pub const EXPLOSION_POOL_SIZE: usize = 12;
+pub const EXPLOSION_MERGE_DISTANCE: f32 = 20.0;
+pub const EXPLOSION_LIGHT_TIME: f32 = 1.0;
+pub const EXPLOSION_MAX_INTENSITY: f32 = 200000.0;
 
 pub const SAM_MAX_SPEED: f32 = 52.0;
 pub const SAM_ACCELERATION: f32 = 50.0;
@@ -12,4 +22,90 @@ pub const SANTA_MISSILE_RANGE: f32 = 150.0;
 pub const SANTA_TURN_SPEED: f32 = 2.5;
 
 pub const HOUSE_RADIUS: i32 = 100;
-pub const HOUSE_SPAWN_DISTANCE: f32 = 50.0;
\ No newline at end of file
+pub const HOUSE_SPAWN_DISTANCE: f32 = 50.0;
+pub const HOUSE_MIN_SPACING: f32 = 30.0;
+pub const STREET_WIDTH: f32 = 12.0;
+pub const TREE_CLEARANCE: f32 = 20.0;
+pub const SAM_MIN_SPACING: f32 = 15.0;
+pub const SAM_HOUSE_SPACING: f32 = 20.0;
+
+pub const TERRAIN_SEED: u32 = 2412;
+pub const TERRAIN_CHUNK_SIZE: f32 = 256.0;
+pub const TERRAIN_CHUNK_RESOLUTION: u32 = 32;
+pub const TERRAIN_AMPLITUDE: f32 = 60.0;
+pub const TERRAIN_NOISE_SCALE: f32 = 400.0;
+pub const TERRAIN_FLAT_RADIUS: f32 = 300.0;
+pub const TERRAIN_MAX_HEIGHT: f32 = 25.0;
+pub const TERRAIN_FLATTEN_FALLOFF: f32 = 60.0;
+pub const VILLAGE_FLAT_MARGIN: f32 = 20.0;
+pub const SANTA_GROUND_CLEARANCE: f32 = 1.0;
+pub const SANTA_FLIGHT_HEIGHT: f32 = 0.0;
+pub const SANTA_SETTLE_SPEED: f32 = 2.0;
+pub const LAKE_LEVEL: f32 = -6.0;
+pub const TERRAIN_CRASH_DAMAGE: i32 = 10;
+
+pub const CHUNK_VIEW_RADIUS: i32 = 4;
+pub const CHUNK_UNLOAD_RADIUS: i32 = 6;
+pub const CHUNKS_SPAWNED_PER_FRAME: usize = 2;
+pub const MAX_LIVE_CHUNK_ENTITIES: usize = 3000;
+pub const CHIMNEY_DROP_DISTANCE: f32 = 25.0;
+
+pub const CAMERA_BLEND_TIME: f32 = 1.0;
+pub const CAMERA_ORBIT_SPEED: f32 = 0.3;
+pub const CAMERA_COLLISION_RADIUS: f32 = 1.0;
+pub const CAMERA_SHAKE_RADIUS: f32 = 80.0;
+pub const CAMERA_SHAKE_MAX_OFFSET: f32 = 2.0;
+pub const CAMERA_TRAUMA_DECAY: f32 = 1.5;
+pub const GIFT_CAM_IMPACT_TIME: f32 = 1.5;
+pub const GIFT_CAM_SLOW_MOTION: f32 = 0.25;
+pub const PHOTO_FLY_SPEED: f32 = 30.0;
+pub const PHOTO_TURN_SPEED: f32 = 1.0;
+
+pub const RADAR_SIZE: f32 = 200.0;
+pub const RADAR_RANGES: [f32; 4] = [250.0, 500.0, 1000.0, 2000.0];
+pub const EDGE_ARROW_MARGIN: f32 = 32.0;
+pub const EDGE_ARROW_URGENT_DISTANCE: f32 = 200.0;
+pub const EDGE_ARROW_IMAGE_SIZE: u32 = 32;
+pub const THREAT_TRACKING_RANGE: f32 = 300.0;
+pub const THREAT_LOCK_TIME: f32 = 1.5;
+pub const THREAT_LAUNCH_FLASH_TIME: f32 = 2.0;
+pub const THREAT_IMPACT_WARNING_TIME: f32 = 2.0;
+pub const HUD_MAX_ALTITUDE: f32 = 200.0;
+pub const HUD_DAMAGE_FLASH_TIME: f32 = 0.5;
+pub const HUD_DIAL_SIZE: f32 = 64.0;
+pub const HUD_RING_SEGMENTS: usize = 24;
+pub const HUD_RING_SEGMENT_SIZE: f32 = 6.0;
+pub const WORLD_BAR_RANGE: f32 = 300.0;
+
+pub const LAST_LEVEL: u32 = 10;
+
+pub const NAUGHTY_BASE_CHANCE: f32 = 0.1;
+pub const NAUGHTY_CHANCE_PER_LEVEL: f32 = 0.03;
+pub const NAUGHTY_MAX_CHANCE: f32 = 0.35;
+pub const EMPTY_HOUSE_CHANCE: f32 = 0.1;
+pub const NOSE_SCAN_RANGE: f32 = 200.0;
+pub const NOSE_SCAN_ANGLE: f32 = 0.3;
+pub const COAL_SCORE: u32 = 150;
+pub const WRONG_DELIVERY_PENALTY: u32 = 200;
+pub const WRONG_DELIVERY_SPIRIT_LOSS: i32 = 10;
+
+pub const DUSK_HOUR: u8 = 17;
+pub const DAWN_HOUR: u8 = 7;
+pub const NIGHT_LENGTH_SECONDS: f32 = 1800.0;
+pub const LEVEL_BASE_TIME_LIMIT: f32 = 60.0;
+pub const LEVEL_TIME_LIMIT_PER_LEVEL: f32 = 20.0;
+pub const LATE_DELIVERY_FACTOR: f32 = 0.5;
+
+pub const SUN_ILLUMINANCE: f32 = 8000.0;
+pub const MOON_ILLUMINANCE: f32 = 300.0;
+pub const NIGHT_AMBIENT_BRIGHTNESS: f32 = 0.02;
+pub const TWILIGHT_AMBIENT_BRIGHTNESS: f32 = 0.2;
+
+pub const STORM_DURATION: f32 = 30.0;
+pub const STORM_WIND_MULTIPLIER: f32 = 2.5;
+pub const BLIZZARD_VISIBILITY: f32 = 250.0;
+pub const WEATHER_CHANGE_RATE: f32 = 0.3;
+pub const SANTA_WIND_FACTOR: f32 = 0.5;
+pub const GIFT_WIND_FACTOR: f32 = 1.0;
+pub const MISSILE_WIND_FACTOR: f32 = 0.2;
+pub const SETTINGS_SAVE_DELAY: f32 = 1.0;
@@ -0,0 +1,272 @@
+use belly::build::{eml, FromWorldAndParams, widget, WidgetContext};
+use belly::core::eml::Params;
+use belly::prelude::*;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use crate::camera::GameCamera;
+use crate::constants::{EDGE_ARROW_IMAGE_SIZE, EDGE_ARROW_MARGIN, EDGE_ARROW_URGENT_DISTANCE};
+use crate::sam_site::{SamTarget, SurfaceToAirMissile};
+use crate::santa::{Santa, SantaHasTarget};
+use crate::ui::UiResources;
+use crate::villages::VillageCenter;
+
+pub struct EdgeArrowPlugin;
+
+impl Plugin for EdgeArrowPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, (edge_arrow_style, make_arrow_image))
+            .add_systems(Update, edge_arrow_system)
+        ;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EdgeArrowKind {
+    Target,
+    Village,
+    Missile,
+}
+
+/// Points at something that is off screen, clamped to the edge. On-screen targets
+/// are left to the `Fellow` widget.
+#[derive(Component)]
+pub struct EdgeArrow {
+    pub target: Entity,
+    /// The image that gets turned to point at the target
+    pub head: Entity,
+    pub label: String,
+}
+
+/// White arrow pointing right, tinted and rotated per arrow
+#[derive(Resource)]
+pub struct EdgeArrowImage(pub Handle<Image>);
+
+#[widget]
+#[param(target: Entity => EdgeArrow: target)]
+#[param(head: Entity => EdgeArrow: head)]
+fn edge_arrow(ctx: &mut WidgetContext) {
+    let content = ctx.content();
+    ctx.render(eml! {
+        <span s:left=managed() s:top=managed() s:display=managed() s:position-type="absolute">
+            {content}
+        </span>
+    })
+}
+
+impl FromWorldAndParams for EdgeArrow {
+    fn from_world_and_params(_: &mut World, params: &mut Params) -> Self {
+        EdgeArrow {
+            target: params.try_get("target").expect("Missing required `target` param"),
+            head: params.try_get("head").expect("Missing required `head` param"),
+            label: String::new(),
+        }
+    }
+}
+
+fn edge_arrow_style(mut commands: Commands) {
+    commands.add(ess! {
+        .edge_arrow {
+            padding: 4px;
+            align-items: center;
+            font: bold;
+            font-size: 14px;
+            color: white;
+        }
+        .edge_arrow_head {
+            width: 24px;
+            height: 24px;
+            margin-right: 4px;
+        }
+    });
+}
+
+/// An arrow pointing along +x: a short shaft with a head on the right half
+pub fn arrow_image(size: u32) -> Image {
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let u = (x as f32 + 0.5) / size as f32;
+            let v = ((y as f32 + 0.5) / size as f32 - 0.5).abs();
+            let shaft = u < 0.5 && v < 0.12;
+            let head = u >= 0.4 && v <= (1.0 - u) / 0.6 * 0.5;
+            let alpha = if shaft || head { 255 } else { 0 };
+            data.extend_from_slice(&[255, 255, 255, alpha]);
+        }
+    }
+    Image::new(
+        Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn make_arrow_image(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    commands.insert_resource(EdgeArrowImage(images.add(arrow_image(EDGE_ARROW_IMAGE_SIZE))));
+}
+
+/// Where on the screen edge an arrow pointing at `local` (camera space) should sit.
+/// Works for things behind the camera too, they end up on the side they are on.
+pub fn edge_position(local: Vec3, screen_size: Vec2, margin: f32) -> Vec2 {
+    let half = screen_size / 2.0;
+    let direction = Vec2::new(local.x, -local.y).try_normalize().unwrap_or(Vec2::Y);
+    let scale_x = if direction.x != 0.0 { (half.x - margin) / direction.x.abs() } else { f32::MAX };
+    let scale_y = if direction.y != 0.0 { (half.y - margin) / direction.y.abs() } else { f32::MAX };
+    half + direction * scale_x.min(scale_y).max(0.0)
+}
+
+/// Rotation around z that turns the +x arrow image towards `direction`. UI y points
+/// down, so this is clockwise on screen.
+pub fn arrow_rotation(direction: Vec2) -> Quat {
+    Quat::from_rotation_z(direction.y.atan2(direction.x))
+}
+
+/// 0.0 when far away, 1.0 when it is on top of Santa
+fn urgency(distance: f32) -> f32 {
+    (1.0 - distance / EDGE_ARROW_URGENT_DISTANCE).clamp(0.0, 1.0)
+}
+
+fn edge_arrow_system(
+    mut commands: Commands,
+    mut elements: Elements,
+    mut arrows: Query<(Entity, &mut EdgeArrow, &mut Style, &Node)>,
+    mut heads: Query<(&mut Transform, &mut BackgroundColor)>,
+    transforms: Query<&GlobalTransform>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    santa_query: Query<(Entity, &GlobalTransform, Option<&SantaHasTarget>), With<Santa>>,
+    missile_query: Query<(Entity, &SamTarget), With<SurfaceToAirMissile>>,
+    village_query: Query<(Entity, &VillageCenter, &GlobalTransform)>,
+    ui_resources: Res<UiResources>,
+    arrow_image: Res<EdgeArrowImage>,
+) {
+    let (Ok((camera, camera_transform)), Ok((santa_entity, santa_transform, santa_target))) = (camera_q.get_single(), santa_query.get_single()) else {
+        return;
+    };
+    let Some(screen_size) = camera.logical_viewport_size() else {
+        return;
+    };
+    let santa_position = santa_transform.translation();
+
+    let mut wanted = Vec::new();
+    if let Some(santa_target) = santa_target {
+        wanted.push((santa_target.target, EdgeArrowKind::Target));
+    }
+    let next_village = village_query
+        .iter()
+        .filter(|(_, village, _)| village.needs_gifts)
+        .map(|(entity, _, transform)| (entity, transform.translation().distance_squared(santa_position)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    if let Some((village_entity, _)) = next_village {
+        wanted.push((village_entity, EdgeArrowKind::Village));
+    }
+    for (missile_entity, target) in missile_query.iter() {
+        if target.0 == santa_entity {
+            wanted.push((missile_entity, EdgeArrowKind::Missile));
+        }
+    }
+
+    let world_to_camera = camera_transform.compute_matrix().inverse();
+    for (entity, mut arrow, mut style, node) in arrows.iter_mut() {
+        let kind = wanted.iter().find(|(target, _)| *target == arrow.target).map(|(_, kind)| *kind);
+        let (Some(kind), Ok(target_transform)) = (kind, transforms.get(arrow.target)) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let position = target_transform.translation();
+        let on_screen = camera
+            .world_to_viewport(camera_transform, position)
+            .map_or(false, |pos| pos.cmpge(Vec2::ZERO).all() && pos.cmple(screen_size).all());
+        if on_screen {
+            style.display = Display::None;
+            continue;
+        }
+        let local = world_to_camera.transform_point3(position);
+        let edge = edge_position(local, screen_size, EDGE_ARROW_MARGIN);
+        let distance = position.distance(santa_position);
+        let urgency = urgency(distance);
+        let label = format!("{:.0}m", distance);
+        if arrow.label != label {
+            arrow.label = label;
+        }
+        // Everything goes solid as it gets close, missiles also go from yellow to red
+        let (color, far_alpha) = match kind {
+            EdgeArrowKind::Target => (ui_resources.target_color, 0.6),
+            EdgeArrowKind::Village => (Color::WHITE, 0.3),
+            EdgeArrowKind::Missile => (Color::rgb(1.0, 1.0 - urgency, 0.0), 0.4),
+        };
+        if let Ok((mut head_transform, mut head_color)) = heads.get_mut(arrow.head) {
+            head_transform.rotation = arrow_rotation(edge - screen_size / 2.0);
+            head_color.0 = color.with_a(far_alpha + (1.0 - far_alpha) * urgency);
+        }
+        style.display = Display::Flex;
+        style.left = Val::Px((edge.x - 0.5 * node.size().x).round());
+        style.top = Val::Px((edge.y - 0.5 * node.size().y).round());
+    }
+
+    for (target, _) in wanted {
+        if arrows.iter().any(|(_, arrow, ..)| arrow.target == target) {
+            continue;
+        }
+        let arrow = commands.spawn_empty().id();
+        let head = commands.spawn(UiImage::new(arrow_image.0.clone())).id();
+        elements.select("body").add_child(eml! {
+            <edge_arrow {arrow} target=target head=head c:edge_arrow>
+                <span {head} c:edge_arrow_head/>
+                <label bind:value=from!(arrow, EdgeArrow:label)/>
+            </edge_arrow>
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 0.001;
+    const SCREEN: Vec2 = Vec2::new(800.0, 600.0);
+
+    #[test]
+    fn arrows_sit_on_the_side_the_target_is_on() {
+        let cases = [
+            (Vec3::new(1.0, 0.0, -5.0), Vec2::new(790.0, 300.0)),
+            (Vec3::new(-1.0, 0.0, -5.0), Vec2::new(10.0, 300.0)),
+            (Vec3::new(0.0, 1.0, -5.0), Vec2::new(400.0, 10.0)),
+            (Vec3::new(0.0, -1.0, -5.0), Vec2::new(400.0, 590.0)),
+            // The short side wins on a diagonal
+            (Vec3::new(1.0, -1.0, -5.0), Vec2::new(690.0, 590.0)),
+        ];
+        for (local, expected) in cases {
+            let edge = edge_position(local, SCREEN, 10.0);
+            assert!(edge.distance(expected) < TOLERANCE, "{} ended up at {}", local, edge);
+        }
+    }
+
+    #[test]
+    fn things_behind_the_camera_still_get_an_arrow() {
+        let edge = edge_position(Vec3::new(-3.0, 0.0, 20.0), SCREEN, 10.0);
+        assert!(edge.distance(Vec2::new(10.0, 300.0)) < TOLERANCE, "behind and to the left ended up at {}", edge);
+        // Dead behind has no side, it goes to the bottom
+        let edge = edge_position(Vec3::new(0.0, 0.0, 20.0), SCREEN, 10.0);
+        assert!(edge.distance(Vec2::new(400.0, 590.0)) < TOLERANCE, "dead behind ended up at {}", edge);
+    }
+
+    #[test]
+    fn the_arrow_turns_towards_the_edge() {
+        for direction in [Vec2::X, Vec2::Y, Vec2::NEG_X, Vec2::NEG_Y, Vec2::new(1.0, -1.0).normalize()] {
+            let pointing = arrow_rotation(direction).mul_vec3(Vec3::X).truncate();
+            assert!(pointing.distance(direction) < TOLERANCE, "{} pointed at {}", direction, pointing);
+        }
+    }
+
+    #[test]
+    fn the_arrow_image_points_right() {
+        let size = 32;
+        let image = arrow_image(size);
+        let alpha = |x: u32, y: u32| image.data[((y * size + x) * 4 + 3) as usize];
+        assert_eq!(alpha(size - 2, size / 2), 255, "tip");
+        assert_eq!(alpha(1, size / 2), 255, "tail");
+        assert_eq!(alpha(size - 2, 1), 0, "top right corner");
+        assert_eq!(alpha(1, 1), 0, "top left corner");
+    }
+}
@@ -1,72 +1,177 @@
-use bevy::app::{App, Plugin, Startup};
+use std::f32::consts::PI;
+use bevy::app::{App, Plugin, Startup, Update};
 use bevy::core::Name;
-use bevy::math::{EulerRot, Quat};
-use bevy::pbr::{CascadeShadowConfigBuilder, DirectionalLight, DirectionalLightBundle};
-use bevy::prelude::{Color, Commands, default, Transform};
+use bevy::math::Vec3;
+use bevy::pbr::{AmbientLight, DirectionalLight, DirectionalLightBundle, FogSettings};
+use bevy::prelude::{ClearColor, Color, Commands, Component, default, Query, Res, ResMut, Transform, With};
+use crate::camera::GameCamera;
+use crate::christmas_eve::ChristmasEveClock;
+use crate::lights::shadow_cascades;
+use crate::constants::{DUSK_HOUR, MOON_ILLUMINANCE, NIGHT_AMBIENT_BRIGHTNESS, SUN_ILLUMINANCE, TWILIGHT_AMBIENT_BRIGHTNESS};
 
 pub struct EnvironmentPlugin;
 
 impl Plugin for EnvironmentPlugin {
     fn build(&self, app: &mut App) {
         app
-            // .add_plugins(AtmospherePlugin)
-            // .insert_resource(AtmosphereModel::new(Nishita {
-            //     sun_position: Vec3::new(1.0, 0.5, 1.0),
-            //     ..default() }))
-            // .insert_resource(CycleTimer(Timer::new(
-            //     Duration::from_millis(500), // Update our atmosphere every 50ms (in a real game, this would be much slower, but for the sake of an example we use a faster update)
-            //     TimerMode::Repeating,
-            // )))
+            .insert_resource(ClearColor(NIGHT_SKY))
+            .insert_resource(AmbientLight {
+                color: MOONLIGHT,
+                brightness: NIGHT_AMBIENT_BRIGHTNESS,
+            })
             .add_systems(Startup, (
                 spawn_lights,
             ))
-            // .add_systems(Update, daylight_cycle)
+            .add_systems(Update, daylight_cycle)
         ;
     }
 }
 
-// fn daylight_cycle(
-//     mut atmosphere: AtmosphereMut<Nishita>,
-//     mut timer: ResMut<CycleTimer>,
-//     time: Res<Time>,
-// ) {
-//     timer.0.tick(time.delta());
-//
-//     if timer.0.finished() {
-//         // let t = (time.elapsed_seconds_wrapped() / 50.0) / 2.0;
-//         atmosphere.sun_position = Quat::from_euler(EulerRot::YXZ, 0.0, 5f32.to_radians(), 0.0).mul_vec3(atmosphere.sun_position);
-//     }
-// }
-//
-// // Timer for updating the daylight cycle (updating the atmosphere every frame is slow, so it's better to do incremental changes)
-// #[derive(Resource)]
-// struct CycleTimer(Timer);
+const NIGHT_SKY: Color = Color::rgb(0.01, 0.015, 0.05);
+const TWILIGHT_SKY: Color = Color::rgb(0.35, 0.2, 0.3);
+const DAY_SKY: Color = Color::rgb(0.55, 0.7, 0.9);
+const MOONLIGHT: Color = Color::rgb(0.6, 0.7, 1.0);
+const SUNLIGHT: Color = Color::rgb(1.0, 0.75, 0.5);
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub enum CelestialBody {
+    Sun,
+    Moon,
+}
+
+/// Direction pointing at the sun for a given hour of the day. The sun is at its
+/// highest at noon and goes down right at `DUSK_HOUR`, so the night starts dark.
+fn sun_direction(hours: f32) -> Vec3 {
+    let angle = hours / 24.0 * 2.0 * PI;
+    // Lowers the whole arc so the elevation crosses zero at dusk instead of at six
+    let horizon = ((DUSK_HOUR as f32 - 12.0) / 24.0 * 2.0 * PI).cos();
+    // A little sideways tilt so the sun is never straight up and shadows stay interesting
+    Vec3::new(0.4, -angle.cos() - horizon, angle.sin()).normalize()
+}
+
+/// Night sky when the sun is well below the horizon, a short purple twilight
+/// around sunset and sunrise, and daylight once the sun is up.
+fn sky_color(sun_elevation: f32) -> Color {
+    if sun_elevation < -0.2 {
+        NIGHT_SKY
+    } else if sun_elevation < 0.1 {
+        lerp_color(NIGHT_SKY, TWILIGHT_SKY, (sun_elevation + 0.2) / 0.3)
+    } else {
+        lerp_color(TWILIGHT_SKY, DAY_SKY, ((sun_elevation - 0.1) / 0.3).min(1.0))
+    }
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let from = Vec3::new(from.r(), from.g(), from.b());
+    let to = Vec3::new(to.r(), to.g(), to.b());
+    let color = from.lerp(to, t.clamp(0.0, 1.0));
+    Color::rgb(color.x, color.y, color.z)
+}
+
+/// Moves the sun and moon with the Christmas Eve clock and colours the sky, the
+/// fog and the ambient light to match. The night is kept dark on purpose, the
+/// nose and the explosions are supposed to be the brightest things around.
+fn daylight_cycle(
+    clock: Res<ChristmasEveClock>,
+    mut clear_color: ResMut<ClearColor>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut lights: Query<(&CelestialBody, &mut Transform, &mut DirectionalLight)>,
+    mut fog_query: Query<&mut FogSettings, With<GameCamera>>,
+) {
+    let time_of_day = clock.time_of_day();
+    let hours = time_of_day.hour() as f32
+        + time_of_day.minute() as f32 / 60.0
+        + time_of_day.second() as f32 / 3600.0;
+    let sun = sun_direction(hours);
+    // How far up the sky the sun and the moon are, 0 at the horizon
+    let sun_strength = sun.y.max(0.0);
+    let moon_strength = (-sun.y).max(0.0);
+
+    for (body, mut transform, mut light) in lights.iter_mut() {
+        let (direction, strength, illuminance) = match body {
+            CelestialBody::Sun => (sun, sun_strength, SUN_ILLUMINANCE),
+            CelestialBody::Moon => (-sun, moon_strength, MOON_ILLUMINANCE),
+        };
+        *transform = Transform::IDENTITY.looking_to(-direction, Vec3::Y);
+        light.illuminance = illuminance * strength.sqrt();
+    }
+
+    let sky = sky_color(sun.y);
+    clear_color.0 = sky;
+
+    let twilight = 1.0 - (sun.y.abs() / 0.3).min(1.0);
+    ambient_light.color = lerp_color(MOONLIGHT, SUNLIGHT, sun_strength.max(twilight));
+    ambient_light.brightness = NIGHT_AMBIENT_BRIGHTNESS
+        + (TWILIGHT_AMBIENT_BRIGHTNESS - NIGHT_AMBIENT_BRIGHTNESS) * (sun_strength + twilight).min(1.0);
+
+    for mut fog in fog_query.iter_mut() {
+        fog.color = sky;
+        // The low sun bleeds into the fog around sunset and sunrise
+        fog.directional_light_color = SUNLIGHT.with_a(twilight * 0.5);
+    }
+}
 
 pub fn spawn_lights(
     mut commands: Commands,
 ) {
-    commands.spawn((
-        Name::from("Directional Light"),
-        DirectionalLightBundle {
-            directional_light: DirectionalLight {
-                color: Color::rgb(0.9, 0.9, 0.9),
-                illuminance: 1.0,
-                shadows_enabled: true,
-                ..default()
-            },
-            transform: Transform {
-                rotation: Quat::from_euler(EulerRot::XYZ, -0.5, 0.6, 0.4),
+    for (body, name, color, shadows_enabled) in [
+        (CelestialBody::Sun, "Sun", SUNLIGHT, false),
+        (CelestialBody::Moon, "Moon", MOONLIGHT, true),
+    ] {
+        commands.spawn((
+            Name::from(name),
+            body,
+            DirectionalLightBundle {
+                directional_light: DirectionalLight {
+                    color,
+                    illuminance: 0.0,
+                    shadows_enabled,
+                    ..default()
+                },
+                cascade_shadow_config: shadow_cascades(4),
                 ..default()
-            },
-// The default cascade config is designed to handle large scenes.
-// As this example has a much smaller world, we can tighten the shadow
-// bounds for better visual quality.
-            cascade_shadow_config: CascadeShadowConfigBuilder {
-                first_cascade_far_bound: 4.0,
-                maximum_distance: 10.0,
-                ..default()
-            }
-                .into(),
-            ..default()
-        }));
-}
\ No newline at end of file
+            }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DAWN_HOUR;
+
+    const TOLERANCE: f32 = 0.001;
+
+    fn same_color(a: Color, b: Color) -> bool {
+        Vec3::new(a.r(), a.g(), a.b()).distance(Vec3::new(b.r(), b.g(), b.b())) < TOLERANCE
+    }
+
+    #[test]
+    fn the_sun_sets_at_dusk_and_rises_at_dawn() {
+        assert!(sun_direction(DUSK_HOUR as f32).y.abs() < TOLERANCE);
+        assert!(sun_direction(DAWN_HOUR as f32).y.abs() < TOLERANCE);
+        assert!(sun_direction(DUSK_HOUR as f32 - 0.5).y > 0.0);
+        assert!(sun_direction(DUSK_HOUR as f32 + 0.5).y < 0.0);
+        assert!(sun_direction(DAWN_HOUR as f32 - 0.5).y < 0.0);
+        assert!(sun_direction(DAWN_HOUR as f32 + 0.5).y > 0.0);
+    }
+
+    #[test]
+    fn the_sun_is_lowest_at_midnight() {
+        let midnight = sun_direction(0.0).y;
+        for hour in 1..24 {
+            assert!(sun_direction(hour as f32).y > midnight, "{}:00", hour);
+        }
+    }
+
+    #[test]
+    fn the_sky_goes_from_twilight_to_night_and_back() {
+        assert!(same_color(sky_color(sun_direction(0.0).y), NIGHT_SKY));
+        assert!(same_color(sky_color(sun_direction(12.0).y), DAY_SKY));
+        let dusk = sky_color(sun_direction(DUSK_HOUR as f32).y);
+        let dawn = sky_color(sun_direction(DAWN_HOUR as f32).y);
+        assert!(same_color(dusk, dawn));
+        assert!(!same_color(dusk, NIGHT_SKY) && !same_color(dusk, DAY_SKY));
+        // Around the horizon it is all twilight
+        assert!(same_color(sky_color(0.1), TWILIGHT_SKY));
+    }
+}
@@ -0,0 +1,131 @@
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::core::Name;
+use bevy::pbr::{PointLight, PointLightBundle};
+use bevy::prelude::{Color, Commands, Component, default, Entity, EventReader, IntoSystemConfigs, Query, Res, ResMut, Resource, Time, Transform, Visibility};
+use bevy_turborand::{DelegatedRng, GlobalRng};
+use crate::collisions::SpawnExplosionAt;
+use crate::constants::{EXPLOSION_LIGHT_TIME, EXPLOSION_MAX_INTENSITY, EXPLOSION_MERGE_DISTANCE, EXPLOSION_POOL_SIZE};
+use crate::lights::DynamicLight;
+use crate::particles::{ParticleEffect, ParticleEmitter};
+
+pub struct ExplosionPlugin;
+
+impl Plugin for ExplosionPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ExplosionPool>()
+            .add_systems(Startup, fill_explosion_pool)
+            .add_systems(
+                Update, (
+                    spawn_explosions,
+                    fade_explosions,
+                ).chain(),
+            )
+        ;
+    }
+}
+
+/// Every explosion there is, they are reused instead of spawned per event
+#[derive(Resource, Default)]
+pub struct ExplosionPool {
+    pub explosions: Vec<Entity>,
+}
+
+#[derive(Component)]
+pub struct Explosion {
+    pub time_left: f32,
+    /// What the light starts at, it fades to nothing over `EXPLOSION_LIGHT_TIME`
+    pub intensity: f32,
+}
+
+impl Explosion {
+    pub fn is_active(&self) -> bool {
+        self.time_left > 0.0
+    }
+}
+
+fn fill_explosion_pool(
+    mut commands: Commands,
+    mut explosion_pool: ResMut<ExplosionPool>,
+) {
+    for _n in 0..EXPLOSION_POOL_SIZE {
+        let explosion = commands.spawn((
+            Name::from("Explosion"),
+            Explosion {
+                time_left: 0.0,
+                intensity: 0.0,
+            },
+            ParticleEmitter::idle(ParticleEffect::Explosion),
+            DynamicLight::new(10.0, true),
+            PointLightBundle {
+                point_light: PointLight {
+                    color: Color::rgb(1.0, 0.6, 0.0),
+                    intensity: 0.0,
+                    range: 40.0,
+                    radius: 0.0,
+                    shadows_enabled: false,
+                    ..default()
+                },
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+        )).id();
+        explosion_pool.explosions.push(explosion);
+    }
+}
+
+fn spawn_explosions(
+    mut explosion_reader: EventReader<SpawnExplosionAt>,
+    mut explosions: Query<(&mut Explosion, &mut Transform, &mut ParticleEmitter)>,
+    explosion_pool: Res<ExplosionPool>,
+    mut global_rng: ResMut<GlobalRng>,
+) {
+    for spawn_explosion in explosion_reader.read() {
+        let intensity = (global_rng.f32() + 0.5) * 80000.0;
+
+        // A salvo going off in one spot gets one bigger light, not a dozen small ones
+        let nearby = explosion_pool.explosions.iter().copied().find(|entity| {
+            explosions
+                .get(*entity)
+                .map_or(false, |(explosion, transform, _)| explosion.is_active() && transform.translation.distance(spawn_explosion.position) < EXPLOSION_MERGE_DISTANCE)
+        });
+        // Otherwise a free one, and if the pool is busy the one closest to burning out
+        let Some(entity) = nearby.or_else(|| {
+            explosion_pool.explosions.iter().copied().min_by(|a, b| {
+                let time_left = |entity: &Entity| explosions.get(*entity).map_or(f32::MAX, |(explosion, _, _)| explosion.time_left);
+                time_left(a).total_cmp(&time_left(b))
+            })
+        }) else {
+            continue;
+        };
+        let Ok((mut explosion, mut transform, mut emitter)) = explosions.get_mut(entity) else {
+            continue;
+        };
+        if nearby.is_some() {
+            transform.translation = (transform.translation + spawn_explosion.position) / 2.0;
+            explosion.intensity = (explosion.intensity + intensity).min(EXPLOSION_MAX_INTENSITY);
+        } else {
+            transform.translation = spawn_explosion.position;
+            explosion.intensity = intensity;
+        }
+        explosion.time_left = EXPLOSION_LIGHT_TIME;
+        // Fires after the new position has been propagated, emitters run in `PostUpdate`
+        emitter.fire();
+    }
+}
+
+fn fade_explosions(
+    mut explosions: Query<(&mut Explosion, &mut PointLight)>,
+    time: Res<Time>,
+) {
+    for (mut explosion, mut point_light) in explosions.iter_mut() {
+        if !explosion.is_active() {
+            if point_light.intensity != 0.0 {
+                point_light.intensity = 0.0;
+            }
+            continue;
+        }
+        explosion.time_left -= time.delta_seconds();
+        point_light.intensity = explosion.intensity * (explosion.time_left / EXPLOSION_LIGHT_TIME).max(0.0);
+    }
+}
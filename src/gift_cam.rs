@@ -0,0 +1,223 @@
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::core::Name;
+use bevy::math::{UVec2, Vec3};
+use bevy::prelude::{Added, Camera, Camera3dBundle, Commands, Component, default, Entity, EventReader, GlobalTransform, IntoSystemConfigs, Query, Real, Res, ResMut, Resource, Time, Transform, UiCameraConfig, Virtual, Window, With, Without};
+use bevy::render::camera::Viewport;
+use bevy::window::PrimaryWindow;
+use bevy_xpbd_3d::prelude::LinearVelocity;
+use crate::constants::{GIFT_CAM_IMPACT_TIME, GIFT_CAM_SLOW_MOTION};
+use crate::input::{ControlCommands, Controller};
+use crate::santa::{ParentEntity, Payload, Santa};
+use crate::sam_site::{SamTarget, SurfaceToAirMissile};
+use crate::settings::Settings;
+use crate::villages::{Disposition, GiftDemand, HitZone, HitZoneType, House, HouseEvent, HouseEventType, VillageCenter};
+
+pub struct GiftCamPlugin;
+
+impl Plugin for GiftCamPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<GiftCam>()
+            .add_systems(Startup, spawn_gift_camera)
+            .add_systems(
+                Update, (
+                    start_gift_cam,
+                    skip_gift_cam,
+                    follow_gift,
+                    fit_gift_cam_viewport,
+                ).chain(),
+            )
+        ;
+    }
+}
+
+/// A little picture-in-picture camera riding along with the gifts that matter
+#[derive(Component)]
+pub struct GiftCamera;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GiftCamState {
+    Idle,
+    Following { gift: Entity, completes_village: bool },
+    /// The gift went down the chimney or finished the village, linger on the spot
+    /// in slow motion. Counted in real time.
+    Impact { position: Vec3, time_left: f32 },
+}
+
+#[derive(Resource)]
+pub struct GiftCam {
+    pub enabled: bool,
+    pub state: GiftCamState,
+}
+
+impl Default for GiftCam {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            state: GiftCamState::Idle,
+        }
+    }
+}
+
+fn spawn_gift_camera(mut commands: Commands) {
+    commands.spawn((
+        Name::from("Gift Cam"),
+        GiftCamera,
+        Camera3dBundle {
+            camera: Camera {
+                order: 1,
+                is_active: false,
+                ..default()
+            },
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+    ));
+}
+
+/// Only gifts aimed down the chimney of a nice house or at the last house a village
+/// is waiting for get the cut-in
+fn start_gift_cam(
+    new_gifts: Query<(Entity, &SamTarget, &Payload), Added<SurfaceToAirMissile>>,
+    hit_zone_query: Query<(&ParentEntity, &HitZone)>,
+    house_query: Query<(&House, &Disposition, Option<&GiftDemand>)>,
+    village_query: Query<&VillageCenter>,
+    mut camera_query: Query<&mut Camera, With<GiftCamera>>,
+    mut gift_cam: ResMut<GiftCam>,
+) {
+    if !gift_cam.enabled || gift_cam.state != GiftCamState::Idle {
+        return;
+    }
+    for (gift_entity, target, payload) in new_gifts.iter() {
+        if *payload != Payload::Gift {
+            continue;
+        }
+        let (house_entity, precision) = match hit_zone_query.get(target.0) {
+            Ok((parent, hit_zone)) => (parent.0, hit_zone.zone_type == HitZoneType::Chimney),
+            Err(_) => (target.0, false),
+        };
+        let Ok((house, disposition, gift_demand)) = house_query.get(house_entity) else {
+            continue;
+        };
+        // Gifts for the naughty and the empty houses are nothing to celebrate
+        if *disposition != Disposition::Nice {
+            continue;
+        }
+        let completes_village = gift_demand
+            .filter(|gift_demand| gift_demand.required.saturating_sub(gift_demand.received) == 1)
+            .and_then(|_| village_query.get(house.belongs_to_village).ok())
+            .map_or(false, |village| village.needs_gifts_count == 1);
+        if !precision && !completes_village {
+            continue;
+        }
+
+        for mut camera in camera_query.iter_mut() {
+            camera.is_active = true;
+        }
+        gift_cam.state = GiftCamState::Following { gift: gift_entity, completes_village };
+        return;
+    }
+}
+
+fn end_gift_cam(gift_cam: &mut GiftCam, camera_query: &mut Query<&mut Camera, With<GiftCamera>>, virtual_time: &mut Time<Virtual>) {
+    gift_cam.state = GiftCamState::Idle;
+    virtual_time.set_relative_speed(1.0);
+    for mut camera in camera_query.iter_mut() {
+        camera.is_active = false;
+    }
+}
+
+fn skip_gift_cam(
+    mut santa_query: Query<&mut Controller, With<Santa>>,
+    mut camera_query: Query<&mut Camera, With<GiftCamera>>,
+    mut gift_cam: ResMut<GiftCam>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+) {
+    for mut controller in santa_query.iter_mut() {
+        if controller.triggers.remove(&ControlCommands::SkipGiftCam) && gift_cam.state != GiftCamState::Idle {
+            end_gift_cam(&mut gift_cam, &mut camera_query, &mut virtual_time);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn follow_gift(
+    gift_query: Query<(&GlobalTransform, Option<&LinearVelocity>), Without<GiftCamera>>,
+    mut gift_camera_query: Query<&mut Transform, With<GiftCamera>>,
+    mut camera_query: Query<&mut Camera, With<GiftCamera>>,
+    mut house_er: EventReader<HouseEvent>,
+    mut gift_cam: ResMut<GiftCam>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    real_time: Res<Time<Real>>,
+    settings: Res<Settings>,
+) {
+    match gift_cam.state {
+        GiftCamState::Idle => {}
+        GiftCamState::Following { gift, completes_village } => {
+            let impact = house_er.read().find_map(|house_event| match house_event.0 {
+                HouseEventType::ReceivedGifts { gift: hit_by, zone, impact_point, .. } if hit_by == gift => Some((zone, impact_point)),
+                _ => None,
+            });
+            if let Some((zone, impact_point)) = impact {
+                if zone == HitZoneType::Chimney || completes_village {
+                    gift_cam.state = GiftCamState::Impact { position: impact_point, time_left: GIFT_CAM_IMPACT_TIME };
+                    if settings.slow_motion {
+                        virtual_time.set_relative_speed(GIFT_CAM_SLOW_MOTION);
+                    }
+                } else {
+                    end_gift_cam(&mut gift_cam, &mut camera_query, &mut virtual_time);
+                }
+                return;
+            }
+            let Ok((gift_transform, linear_velocity)) = gift_query.get(gift) else {
+                // Ran out of time or hit the ground, nothing worth slowing down for
+                end_gift_cam(&mut gift_cam, &mut camera_query, &mut virtual_time);
+                return;
+            };
+            let position = gift_transform.translation();
+            let heading = linear_velocity.map_or(Vec3::Z, |velocity| velocity.0.normalize_or_zero());
+            for mut transform in gift_camera_query.iter_mut() {
+                transform.translation = position - heading * 10.0 + Vec3::Y * 3.0;
+                transform.look_at(position, Vec3::Y);
+            }
+        }
+        GiftCamState::Impact { position, time_left } => {
+            let time_left = time_left - real_time.delta_seconds();
+            if time_left <= 0.0 {
+                end_gift_cam(&mut gift_cam, &mut camera_query, &mut virtual_time);
+                return;
+            }
+            for mut transform in gift_camera_query.iter_mut() {
+                transform.look_at(position, Vec3::Y);
+            }
+            gift_cam.state = GiftCamState::Impact { position, time_left };
+        }
+    }
+}
+
+/// A third of the screen down in the bottom right corner, kept up to date when the window is resized
+fn fit_gift_cam_viewport(
+    mut camera_query: Query<&mut Camera, With<GiftCamera>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+) {
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+    let size = UVec2::new(window.physical_width() / 3, window.physical_height() / 3).max(UVec2::ONE);
+    let position = UVec2::new(window.physical_width(), window.physical_height()).saturating_sub(size);
+    for mut camera in camera_query.iter_mut() {
+        if !camera.is_active {
+            continue;
+        }
+        let fits = camera.viewport.as_ref().map_or(false, |viewport| {
+            viewport.physical_position == position && viewport.physical_size == size
+        });
+        if !fits {
+            camera.viewport = Some(Viewport {
+                physical_position: position,
+                physical_size: size,
+                ..default()
+            });
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use belly::prelude::*;
+use bevy::prelude::*;
+use crate::constants::{GROUND_PLANE, HUD_DAMAGE_FLASH_TIME, HUD_DIAL_SIZE, HUD_MAX_ALTITUDE, HUD_RING_SEGMENT_SIZE, HUD_RING_SEGMENTS};
+use crate::input::Controller;
+use crate::santa::{GameEvent, GameEventTypes, Santa, SantaHasTarget, SantaStats};
+use crate::settings::Settings;
+use crate::ui::set_if_changed;
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, hud_style)
+            .add_systems(
+                Update, (
+                    spawn_flight_hud,
+                    flight_hud_system,
+                    damage_flash_system,
+                    compass_system,
+                    gift_ring_system,
+                ).chain(),
+            )
+        ;
+    }
+}
+
+const COMPASS_POINTS: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+
+/// Everything the flight HUD shows, kept up to date on Santa so the widgets can bind to it
+#[derive(Component, Default)]
+pub struct FlightHud {
+    pub speed: f32,
+    pub max_speed: f32,
+    pub speed_text: String,
+    pub altitude: f32,
+    pub altitude_text: String,
+    pub heading: String,
+    /// Degrees clockwise from north
+    pub heading_degrees: f32,
+    /// 1.0 when the next gift is ready to go
+    pub cool_down: f32,
+    pub health: f32,
+    pub health_text: String,
+    /// 1.0 right after a hit, fades to 0.0
+    pub damage_flash: f32,
+    pub last_health: i32,
+}
+
+/// Full screen red overlay for the damage flash
+#[derive(Component)]
+pub struct DamageFlash;
+
+/// The footer cell with all the gauges, replaced on every (re)start
+#[derive(Component)]
+pub struct FlightHudRoot;
+
+/// A letter on the compass dial, the angle is where it is from north
+#[derive(Component)]
+pub struct CompassPoint(pub f32);
+
+/// One dot of the gift ring, they light up clockwise as the next gift gets ready
+#[derive(Component)]
+pub struct GiftRingSegment(pub usize);
+
+fn hud_style(mut commands: Commands) {
+    commands.add(ess! {
+        .flight_hud {
+            flex-direction: column;
+            row-gap: 4px;
+            font-size: 16px;
+            color: white;
+        }
+        .hud_row {
+            column-gap: 8px;
+            align-items: center;
+        }
+        .hud_gauge {
+            width: 160px;
+            height: 10px;
+        }
+        .hud_heading {
+            font-size: 20px;
+        }
+        .hud_dial {
+            width: 64px;
+            height: 64px;
+            background-color: #00000066;
+        }
+        .hud_compass_point {
+            position-type: absolute;
+            font: bold;
+            font-size: 14px;
+        }
+        .hud_compass_nose {
+            position-type: absolute;
+            left: 30px;
+            top: 0px;
+            width: 4px;
+            height: 10px;
+            background-color: #ff2222;
+        }
+        .damage_flash {
+            position-type: absolute;
+            left: 0px;
+            top: 0px;
+            width: 100%;
+            height: 100%;
+        }
+    });
+}
+
+fn spawn_flight_hud(
+    mut commands: Commands,
+    mut elements: Elements,
+    mut game_event: EventReader<GameEvent>,
+    santa_query: Query<(Entity, &Controller, &SantaStats), With<Santa>>,
+    old_widgets: Query<Entity, Or<(With<FlightHudRoot>, With<DamageFlash>)>>,
+) {
+    for game_event in game_event.read() {
+        if !matches!(game_event.event_type, GameEventTypes::Started | GameEventTypes::Restarted) {
+            continue;
+        }
+        let Ok((p, controller, santa_stats)) = santa_query.get_single() else {
+            continue;
+        };
+        for entity in old_widgets.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        commands.entity(p).insert(FlightHud {
+            max_speed: controller.max_speed,
+            last_health: santa_stats.health,
+            ..default()
+        });
+        let max_speed = controller.max_speed;
+        let root = commands.spawn(FlightHudRoot).id();
+        let ring = commands.spawn_empty().id();
+        let [north, east, south, west] = [0.0, 90.0, 180.0, 270.0].map(|angle| commands.spawn(CompassPoint(angle)).id());
+        elements.select("#ui-footer").add_child(eml! {
+            <span {root} c:cell c:flight_hud>
+                <span c:hud_row>
+                    <span c:hud_dial>
+                        <span c:hud_compass_nose/>
+                        <label {north} c:hud_compass_point s:left=managed() s:top=managed() value="N"/>
+                        <label {east} c:hud_compass_point s:left=managed() s:top=managed() value="E"/>
+                        <label {south} c:hud_compass_point s:left=managed() s:top=managed() value="S"/>
+                        <label {west} c:hud_compass_point s:left=managed() s:top=managed() value="W"/>
+                    </span>
+                    <label c:hud_heading bind:value=from!(p, FlightHud:heading)/>
+                </span>
+                <span c:hud_row>
+                    <label value="SPD"/>
+                    <progressbar c:hud_gauge minimum=0.0 maximum=max_speed bind:value=from!(p, FlightHud:speed)/>
+                    <label bind:value=from!(p, FlightHud:speed_text)/>
+                </span>
+                <span c:hud_row>
+                    <label value="ALT"/>
+                    <progressbar c:hud_gauge minimum=0.0 maximum=HUD_MAX_ALTITUDE bind:value=from!(p, FlightHud:altitude)/>
+                    <label bind:value=from!(p, FlightHud:altitude_text)/>
+                </span>
+                <span c:hud_row>
+                    <span {ring} c:hud_dial/>
+                    <label value="GIFT"/>
+                </span>
+                <span c:hud_row>
+                    <label value="HP"/>
+                    <progressbar c:hud_gauge minimum=0.0 maximum=100.0 bind:value=from!(p, FlightHud:health)/>
+                    <label bind:value=from!(p, FlightHud:health_text)/>
+                </span>
+            </span>
+        });
+        // Plain nodes, they never change shape so belly doesn't need to know about them
+        let center = HUD_DIAL_SIZE / 2.0;
+        let segments: Vec<Entity> = (0..HUD_RING_SEGMENTS).map(|index| {
+            let angle = index as f32 * 360.0 / HUD_RING_SEGMENTS as f32;
+            let position = dial_position(angle, center - HUD_RING_SEGMENT_SIZE) + center - HUD_RING_SEGMENT_SIZE / 2.0;
+            commands.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(position.x),
+                        top: Val::Px(position.y),
+                        width: Val::Px(HUD_RING_SEGMENT_SIZE),
+                        height: Val::Px(HUD_RING_SEGMENT_SIZE),
+                        ..default()
+                    },
+                    ..default()
+                },
+                GiftRingSegment(index),
+            )).id()
+        }).collect();
+        commands.entity(ring).push_children(&segments);
+        let flash = commands.spawn(DamageFlash).id();
+        elements.select("body").add_child(eml! {
+            <span {flash} c:damage_flash s:background-color=managed()/>
+        });
+    }
+}
+
+/// Offset from the middle of a dial, 0 degrees is the top and they go round clockwise
+pub fn dial_position(degrees: f32, radius: f32) -> Vec2 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vec2::new(sin, -cos) * radius
+}
+
+/// Santa faces -Z like everything else, north is -Z as well
+pub fn compass_heading(rotation: Quat) -> f32 {
+    let forward = rotation.mul_vec3(Vec3::NEG_Z);
+    forward.x.atan2(-forward.z).to_degrees().rem_euclid(360.0)
+}
+
+fn flight_hud_system(
+    mut santa_query: Query<(&Controller, &Transform, &SantaStats, Option<&SantaHasTarget>, &mut FlightHud), With<Santa>>,
+    time: Res<Time>,
+) {
+    for (controller, transform, santa_stats, santa_target, mut hud) in santa_query.iter_mut() {
+        let speed = controller.speed.abs();
+        let altitude = transform.translation.y - GROUND_PLANE;
+        let heading = compass_heading(transform.rotation);
+        let point = COMPASS_POINTS[(heading / 45.0).round() as usize % COMPASS_POINTS.len()];
+        let cool_down = santa_target.map_or(1.0, |santa_target| {
+            1.0 - santa_target.cool_down * santa_target.rate_of_fire_per_minute / 60.0
+        });
+
+        set_if_changed(&mut hud.speed, speed.min(hud.max_speed));
+        set_if_changed(&mut hud.speed_text, format!("{:.0}", speed));
+        set_if_changed(&mut hud.altitude, altitude.clamp(0.0, HUD_MAX_ALTITUDE));
+        set_if_changed(&mut hud.altitude_text, format!("{:.0}m", altitude));
+        set_if_changed(&mut hud.heading, format!("{} {:03.0}", point, heading));
+        set_if_changed(&mut hud.heading_degrees, heading);
+        set_if_changed(&mut hud.cool_down, cool_down.clamp(0.0, 1.0));
+        set_if_changed(&mut hud.health, santa_stats.health.max(0) as f32);
+        set_if_changed(&mut hud.health_text, format!("{}", santa_stats.health));
+
+        if santa_stats.health < hud.last_health {
+            hud.damage_flash = 1.0;
+        } else if hud.damage_flash > 0.0 {
+            hud.damage_flash = (hud.damage_flash - time.delta_seconds() / HUD_DAMAGE_FLASH_TIME).max(0.0);
+        }
+        set_if_changed(&mut hud.last_health, santa_stats.health);
+    }
+}
+
+fn damage_flash_system(
+    santa_query: Query<&FlightHud, (With<Santa>, Changed<FlightHud>)>,
+    mut flash_query: Query<&mut BackgroundColor, With<DamageFlash>>,
+    settings: Res<Settings>,
+) {
+    for hud in santa_query.iter() {
+        let flash = if settings.flashing { hud.damage_flash } else { 0.0 };
+        for mut background_color in flash_query.iter_mut() {
+            background_color.0 = Color::rgba(1.0, 0.0, 0.0, 0.35 * flash);
+        }
+    }
+}
+
+/// The dial turns with Santa so his nose always points up
+fn compass_system(
+    santa_query: Query<&FlightHud, (With<Santa>, Changed<FlightHud>)>,
+    mut point_query: Query<(&CompassPoint, &mut Style, &Node)>,
+) {
+    let Ok(hud) = santa_query.get_single() else {
+        return;
+    };
+    let center = HUD_DIAL_SIZE / 2.0;
+    for (point, mut style, node) in point_query.iter_mut() {
+        let position = dial_position(point.0 - hud.heading_degrees, center * 0.7) + center - node.size() / 2.0;
+        style.left = Val::Px(position.x.round());
+        style.top = Val::Px(position.y.round());
+    }
+}
+
+fn gift_ring_system(
+    santa_query: Query<&FlightHud, (With<Santa>, Changed<FlightHud>)>,
+    mut segment_query: Query<(&GiftRingSegment, &mut BackgroundColor)>,
+) {
+    let Ok(hud) = santa_query.get_single() else {
+        return;
+    };
+    let lit = ring_segments_lit(hud.cool_down, HUD_RING_SEGMENTS);
+    let color = if lit == HUD_RING_SEGMENTS { Color::GREEN } else { Color::WHITE };
+    for (segment, mut background_color) in segment_query.iter_mut() {
+        background_color.0 = if segment.0 < lit { color } else { Color::rgba(1.0, 1.0, 1.0, 0.15) };
+    }
+}
+
+/// How many dots of the ring are lit for a cool down that is `ready` of the way done
+pub fn ring_segments_lit(ready: f32, segments: usize) -> usize {
+    ((ready.clamp(0.0, 1.0) * segments as f32).floor() as usize).min(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 0.001;
+
+    #[test]
+    fn dials_start_at_the_top_and_go_clockwise() {
+        let cases = [
+            (0.0, Vec2::new(0.0, -10.0)),
+            (90.0, Vec2::new(10.0, 0.0)),
+            (180.0, Vec2::new(0.0, 10.0)),
+            (270.0, Vec2::new(-10.0, 0.0)),
+            (-90.0, Vec2::new(-10.0, 0.0)),
+        ];
+        for (degrees, expected) in cases {
+            let position = dial_position(degrees, 10.0);
+            assert!(position.distance(expected) < TOLERANCE, "{} degrees ended up at {}", degrees, position);
+        }
+    }
+
+    #[test]
+    fn the_compass_reads_where_santa_is_flying() {
+        // Turning left is a positive turn around y
+        let cases = [(0.0, 0.0), (-90.0, 90.0), (180.0, 180.0), (90.0, 270.0)];
+        for (turn, expected) in cases {
+            let heading = compass_heading(Quat::from_rotation_y(f32::to_radians(turn)));
+            let error = (heading - expected).abs();
+            assert!(error < TOLERANCE || (error - 360.0).abs() < TOLERANCE, "turned {} read {}", turn, heading);
+        }
+    }
+
+    #[test]
+    fn the_ring_fills_up_with_the_cool_down() {
+        assert_eq!(ring_segments_lit(0.0, 24), 0);
+        assert_eq!(ring_segments_lit(0.5, 24), 12);
+        assert_eq!(ring_segments_lit(0.99, 24), 23);
+        assert_eq!(ring_segments_lit(1.0, 24), 24);
+        assert_eq!(ring_segments_lit(-1.0, 24), 0);
+        assert_eq!(ring_segments_lit(2.0, 24), 24);
+    }
+}
@@ -1,13 +1,16 @@
 use bevy::app::{App, Plugin, Update};
 use bevy::input::ButtonState;
 use bevy::input::keyboard::KeyboardInput;
-use bevy::prelude::{Component, EventReader, EventWriter, KeyCode, Query, Res, With};
+use bevy::prelude::{Component, EventReader, EventWriter, IntoSystemConfigs, Query, Res, With};
 use bevy::reflect::Reflect;
 use bevy::time::Time;
 use bevy::utils::HashSet;
 use bevy_xpbd_3d::components::{AngularVelocity, LinearVelocity, Rotation};
 use bevy_xpbd_3d::math::Vector3;
+use crate::menus::not_in_menu;
+use crate::photo_mode::not_in_photo_mode;
 use crate::santa::{GameEvent, GameEventTypes};
+use crate::settings::Settings;
 use crate::ui::SillyGameState;
 
 pub struct InputPlugin;
@@ -17,7 +20,7 @@ impl Plugin for InputPlugin {
         app
             .add_systems(
                 Update, (
-                    input_control,
+                    input_control.run_if(not_in_photo_mode).run_if(not_in_menu),
                     kinematic_movement,
                     dynamic_movement,
                 ),
@@ -25,6 +28,8 @@ impl Plugin for InputPlugin {
     }
 }
 
+/// A repeating timer for things that fire over and over, it starts over by itself
+/// every time it runs out. Things that happen once and then go away use `Lifetime`.
 pub trait CoolDown {
     /// Returns true if the cool down is finished
     fn cool_down(&mut self, delta: f32) -> bool;
@@ -38,6 +43,11 @@ pub enum ControlCommands {
     FirePrimary,
     Jump,
     Build,
+    SwitchPayload,
+    SwitchCamera,
+    SkipGiftCam,
+    CycleRadarRange,
+    ToggleMap,
 }
 
 
@@ -136,53 +146,68 @@ pub fn input_control(
     mut query: Query<&mut Controller, With<KeyboardController>>,
     silly_game_state: Res<SillyGameState>,
     mut game_event: EventWriter<GameEvent>,
+    settings: Res<Settings>,
 ) {
+    let keys = &settings.bindings;
     if let Ok(mut controller) = query.get_single_mut() {
         for ev in key_evr.read() {
+            let Some(key) = ev.key_code else {
+                continue;
+            };
             match ev.state {
-                ButtonState::Pressed => match ev.key_code {
-                    Some(KeyCode::B) => {
+                ButtonState::Pressed => match key {
+                    _ if key == keys.build => {
                         if controller.triggers.contains(&ControlCommands::Build) {} else {
                             controller.triggers.insert(ControlCommands::Build);
                         }
                     }
-                    Some(KeyCode::Escape) => {
-                        if controller.triggers.contains(&ControlCommands::Build) {}
+                    _ if key == keys.switch_payload => {
+                        controller.triggers.insert(ControlCommands::SwitchPayload);
                     }
-                    Some(KeyCode::A) => {
+                    _ if key == keys.switch_camera => {
+                        controller.triggers.insert(ControlCommands::SwitchCamera);
+                    }
+                    _ if key == keys.skip_gift_cam => {
+                        controller.triggers.insert(ControlCommands::SkipGiftCam);
+                    }
+                    _ if key == keys.radar_range => {
+                        controller.triggers.insert(ControlCommands::CycleRadarRange);
+                    }
+                    _ if key == keys.map => {
+                        controller.triggers.insert(ControlCommands::ToggleMap);
+                    }
+                    _ if key == keys.turn_left => {
                         controller.rotations.insert(ControlRotation::Left);
                     }
-                    Some(KeyCode::D) => {
+                    _ if key == keys.turn_right => {
                         controller.rotations.insert(ControlRotation::Right);
                     }
-                    Some(KeyCode::W) => {
+                    _ if key == keys.forward => {
                         controller.directions.insert(ControlDirection::Forward);
                     }
-                    Some(KeyCode::S) => {
+                    _ if key == keys.backward => {
                         controller.directions.insert(ControlDirection::Backward);
                     }
-                    Some(KeyCode::Space) => {
+                    _ if key == keys.restart => {
                         if silly_game_state.waiting_for_restart {
                             game_event.send(GameEvent{event_type: GameEventTypes::Restarted});
                         }
                     }
                     _ => {}
                 },
-                ButtonState::Released => match ev.key_code {
-                    Some(KeyCode::A) => {
+                ButtonState::Released => match key {
+                    _ if key == keys.turn_left => {
                         controller.rotations.remove(&ControlRotation::Left);
                     }
-                    Some(KeyCode::D) => {
+                    _ if key == keys.turn_right => {
                         controller.rotations.remove(&ControlRotation::Right);
                     }
-                    Some(KeyCode::W) => {
+                    _ if key == keys.forward => {
                         controller.directions.remove(&ControlDirection::Forward);
                     }
-                    Some(KeyCode::S) => {
+                    _ if key == keys.backward => {
                         controller.directions.remove(&ControlDirection::Backward);
                     }
-                    Some(KeyCode::Left) => {}
-                    Some(KeyCode::Right) => {}
                     _ => {}
                 }
             }
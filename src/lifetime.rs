@@ -0,0 +1,148 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::hierarchy::DespawnRecursiveExt;
+use bevy::prelude::{Commands, Component, Entity, EventWriter, GlobalTransform, Query, Res, Time, Virtual};
+use crate::collisions::SpawnExplosionAt;
+
+pub struct LifetimePlugin;
+
+impl Plugin for LifetimePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, tick_lifetimes)
+        ;
+    }
+}
+
+/// What happens when a lifetime runs out, on top of the entity being despawned
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OnExpire {
+    /// Out of fuel, goes off where it is
+    Explode,
+}
+
+/// Despawns the entity when it runs out. Counts virtual time so pausing
+/// and slow motion stretch it like everything else.
+#[derive(Component)]
+pub struct Lifetime {
+    pub duration: f32,
+    pub time_left: f32,
+    pub on_expire: Option<OnExpire>,
+}
+
+impl Lifetime {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            time_left: duration,
+            on_expire: None,
+        }
+    }
+
+    pub fn exploding(self) -> Self {
+        Self {
+            on_expire: Some(OnExpire::Explode),
+            ..self
+        }
+    }
+
+    /// How far through its life it is, 0.0 when born and 1.0 when it expires
+    pub fn fraction(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return 1.0;
+        }
+        (1.0 - self.time_left / self.duration).clamp(0.0, 1.0)
+    }
+
+    /// Returns true when the lifetime has run out
+    pub fn tick(&mut self, delta: f32) -> bool {
+        self.time_left -= delta;
+        self.time_left <= 0.0
+    }
+}
+
+fn tick_lifetimes(
+    mut commands: Commands,
+    mut lifetimes: Query<(Entity, &mut Lifetime, Option<&GlobalTransform>)>,
+    mut explosion_ew: EventWriter<SpawnExplosionAt>,
+    time: Res<Time<Virtual>>,
+) {
+    for (entity, mut lifetime, global_transform) in lifetimes.iter_mut() {
+        if !lifetime.tick(time.delta_seconds()) {
+            continue;
+        }
+        if let (Some(OnExpire::Explode), Some(global_transform)) = (lifetime.on_expire, global_transform) {
+            explosion_ew.send(SpawnExplosionAt {
+                position: global_transform.translation(),
+            });
+        }
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::app::App;
+    use bevy::ecs::event::Events;
+    use bevy::math::Vec3;
+    use bevy::prelude::Transform;
+    use bevy::time::{TimePlugin, TimeUpdateStrategy};
+    use super::*;
+
+    /// Exact in binary, so the sums come out exactly on the lifetime
+    const FRAME_TIME: f32 = 0.125;
+
+    fn headless_app() -> App {
+        let mut app = App::new();
+        app
+            .add_plugins((TimePlugin, LifetimePlugin))
+            .add_event::<SpawnExplosionAt>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(FRAME_TIME)));
+        app
+    }
+
+    fn virtual_elapsed(app: &App) -> f32 {
+        app.world.resource::<Time<Virtual>>().elapsed_seconds()
+    }
+
+    #[test]
+    fn expires_after_exactly_its_duration_and_not_during_a_pause() {
+        let mut app = headless_app();
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        let missile = app.world.spawn((
+            Lifetime::new(1.0).exploding(),
+            GlobalTransform::from(Transform::from_translation(position)),
+        )).id();
+
+        for _ in 0..3 {
+            app.update();
+        }
+        app.world.resource_mut::<Time<Virtual>>().pause();
+        let time_left = app.world.get::<Lifetime>(missile).unwrap().time_left;
+        for _ in 0..50 {
+            app.update();
+        }
+        assert_eq!(app.world.get::<Lifetime>(missile).unwrap().time_left, time_left, "ticked while paused");
+        app.world.resource_mut::<Time<Virtual>>().unpause();
+
+        while app.world.get_entity(missile).is_some() {
+            assert!(virtual_elapsed(&app) < 1.0, "still alive after {} seconds", virtual_elapsed(&app));
+            app.update();
+        }
+        assert_eq!(virtual_elapsed(&app), 1.0);
+
+        let events = app.world.resource::<Events<SpawnExplosionAt>>();
+        let explosions: Vec<Vec3> = events.get_reader().read(events).map(|explosion| explosion.position).collect();
+        assert_eq!(explosions, vec![position]);
+    }
+
+    #[test]
+    fn only_exploding_lifetimes_explode() {
+        let mut app = headless_app();
+        let particle = app.world.spawn((Lifetime::new(0.5), GlobalTransform::default())).id();
+        while app.world.get_entity(particle).is_some() {
+            app.update();
+        }
+        assert!(app.world.resource::<Events<SpawnExplosionAt>>().is_empty());
+    }
+}
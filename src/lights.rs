@@ -0,0 +1,110 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::pbr::{CascadeShadowConfig, CascadeShadowConfigBuilder, PointLight};
+use bevy::prelude::{default, Component, Entity, GlobalTransform, Query, Res, Resource, Visibility, With};
+use crate::camera::GameCamera;
+use crate::constants::{MAX_DYNAMIC_LIGHTS, MAX_SHADOWED_LIGHTS};
+
+pub struct LightBudgetPlugin;
+
+impl Plugin for LightBudgetPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(LightBudget::new(MAX_DYNAMIC_LIGHTS, MAX_SHADOWED_LIGHTS))
+            .add_systems(Update, enforce_light_budget)
+        ;
+    }
+}
+
+/// How many point lights may shine at once and how many of those get shadows
+#[derive(Resource)]
+pub struct LightBudget {
+    pub max_active: usize,
+    pub max_shadowed: usize,
+}
+
+impl LightBudget {
+    pub fn new(max_active: usize, max_shadowed: usize) -> Self {
+        Self {
+            max_active,
+            max_shadowed,
+        }
+    }
+}
+
+/// A point light the budget is allowed to switch off. Lights with zero
+/// intensity are off anyway and don't count against the budget.
+#[derive(Component)]
+pub struct DynamicLight {
+    /// Explosions matter more than a lit window
+    pub importance: f32,
+    pub wants_shadows: bool,
+}
+
+impl DynamicLight {
+    pub fn new(importance: f32, wants_shadows: bool) -> Self {
+        Self {
+            importance,
+            wants_shadows,
+        }
+    }
+}
+
+/// Brighter, closer and more important lights win
+pub fn light_priority(importance: f32, intensity: f32, range: f32, distance: f32) -> f32 {
+    importance * intensity.sqrt() / (1.0 + distance / range.max(1.0))
+}
+
+fn enforce_light_budget(
+    mut lights: Query<(Entity, &DynamicLight, &GlobalTransform, &mut PointLight, &mut Visibility)>,
+    camera_query: Query<&GlobalTransform, With<GameCamera>>,
+    light_budget: Res<LightBudget>,
+) {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let mut ranked: Vec<(Entity, f32)> = lights
+        .iter()
+        .filter(|(_, _, _, point_light, _)| point_light.intensity > 0.0)
+        .map(|(entity, dynamic_light, global_transform, point_light, _)| {
+            let distance = global_transform.translation().distance(camera_transform.translation());
+            (entity, light_priority(dynamic_light.importance, point_light.intensity, point_light.range, distance))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut shadowed = 0;
+    for (rank, (entity, _)) in ranked.iter().enumerate() {
+        let Ok((_, dynamic_light, _, mut point_light, mut visibility)) = lights.get_mut(*entity) else {
+            continue;
+        };
+        let wanted = if rank < light_budget.max_active { Visibility::Inherited } else { Visibility::Hidden };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+        let shadows = dynamic_light.wants_shadows && rank < light_budget.max_active && shadowed < light_budget.max_shadowed;
+        if shadows {
+            shadowed += 1;
+        }
+        if point_light.shadows_enabled != shadows {
+            point_light.shadows_enabled = shadows;
+        }
+    }
+    // Lights that went dark are hidden so they cost nothing
+    for (_, _, _, point_light, mut visibility) in lights.iter_mut() {
+        if point_light.intensity <= 0.0 && *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+/// Shadow cascades for the sun and the moon. Santa flies fast and high, so the
+/// shadows need to reach a fair bit.
+pub fn shadow_cascades(num_cascades: usize) -> CascadeShadowConfig {
+    CascadeShadowConfigBuilder {
+        num_cascades,
+        first_cascade_far_bound: 50.0,
+        maximum_distance: 500.0,
+        ..default()
+    }
+        .into()
+}
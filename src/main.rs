@@ -7,8 +7,25 @@ mod assets;
 mod sam_site;
 mod collisions;
 mod villages;
+mod village_layout;
 mod constants;
 mod ui;
+mod terrain;
+mod chunks;
+mod christmas_eve;
+mod weather;
+mod particles;
+mod lights;
+mod explosions;
+mod lifetime;
+mod gift_cam;
+mod photo_mode;
+mod radar;
+mod edge_arrows;
+mod threats;
+mod hud;
+mod menus;
+mod settings;
 
 use bevy::{prelude::*};
 use bevy::asset::AssetMetaCheck;
@@ -17,13 +34,30 @@ use bevy_turborand::prelude::RngPlugin;
 use bevy_xpbd_3d::plugins::{PhysicsPlugins};
 use crate::assets::AssetsPlugin;
 use crate::camera::CameraPlugin;
+use crate::christmas_eve::ChristmasEvePlugin;
+use crate::chunks::ChunkPlugin;
+use crate::edge_arrows::EdgeArrowPlugin;
 use crate::collisions::CollisionsPlugin;
+use crate::environment::EnvironmentPlugin;
+use crate::explosions::ExplosionPlugin;
+use crate::gift_cam::GiftCamPlugin;
+use crate::hud::HudPlugin;
 use crate::input::InputPlugin;
+use crate::lifetime::LifetimePlugin;
+use crate::menus::MenuPlugin;
+use crate::photo_mode::PhotoModePlugin;
+use crate::radar::RadarPlugin;
+use crate::lights::LightBudgetPlugin;
+use crate::particles::ParticlePlugin;
 use crate::sam_site::SamSitePlugin;
 use crate::santa::SantaPlugin;
+use crate::settings::SettingsPlugin;
 use crate::snow::SnowPlugin;
+use crate::terrain::TerrainPlugin;
+use crate::threats::ThreatPlugin;
 use crate::ui::UiPlugin;
 use crate::villages::VillagePlugin;
+use crate::weather::WeatherPlugin;
 
 fn main() {
     App::new()
@@ -48,17 +82,33 @@ impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app
             .add_plugins(AssetsPlugin)
+            .add_plugins(SettingsPlugin)
             .add_plugins(PhysicsPlugins::default())
             .add_plugins(RngPlugin::default())
-            // .add_plugins(EnvironmentPlugin)
+            .add_plugins(EnvironmentPlugin)
+            .add_plugins(WeatherPlugin)
             .add_plugins(SnowPlugin)
+            .add_plugins(LifetimePlugin)
+            .add_plugins(ParticlePlugin)
+            .add_plugins(LightBudgetPlugin)
+            .add_plugins(ExplosionPlugin)
             .add_plugins(CameraPlugin)
+            .add_plugins(GiftCamPlugin)
+            .add_plugins(PhotoModePlugin)
+            .add_plugins(TerrainPlugin)
+            .add_plugins(ChunkPlugin)
             .add_plugins(VillagePlugin)
             .add_plugins(SantaPlugin)
             .add_plugins(InputPlugin)
             .add_plugins(SamSitePlugin)
             .add_plugins(CollisionsPlugin)
             .add_plugins(UiPlugin)
+            .add_plugins(RadarPlugin)
+            .add_plugins(EdgeArrowPlugin)
+            .add_plugins(ThreatPlugin)
+            .add_plugins(HudPlugin)
+            .add_plugins(MenuPlugin)
+            .add_plugins(ChristmasEvePlugin)
             // .add_plugins(PhysicsDebugPlugin::default())
         ;
     }
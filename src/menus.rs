@@ -0,0 +1,404 @@
+use belly::prelude::*;
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::PhysicsLoop;
+use crate::constants::LAST_LEVEL;
+use crate::input::Controller;
+use crate::photo_mode::not_in_photo_mode;
+use crate::sam_site::{SamSite, SurfaceToAirMissile};
+use crate::santa::{GameEvent, GameEventTypes, Santa};
+use crate::settings::{storage, Settings};
+use crate::villages::{GameTracker, House, LoadLevel, VillageCenter};
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<MenuAction>()
+            .insert_resource(MenuState::load())
+            .add_systems(Startup, spawn_menu_model)
+            .add_systems(
+                Update, (
+                    menu_keys.run_if(not_in_photo_mode),
+                    handle_menu_actions,
+                    track_levels,
+                    update_menu_model,
+                    show_menu_screen,
+                ).chain(),
+            )
+        ;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MenuScreen {
+    /// Playing, no menu up
+    None,
+    Main,
+    LevelSelect,
+    Settings,
+    Pause,
+}
+
+/// Whatever a menu button can do
+#[derive(Event, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MenuAction {
+    Play,
+    Continue,
+    LevelSelect,
+    PreviousLevel,
+    NextLevel,
+    StartSelectedLevel,
+    Settings,
+    CycleQuality,
+    CycleVolume,
+    CycleUiScale,
+    ToggleScreenShake,
+    ToggleFlashing,
+    ToggleGiftCam,
+    Back,
+    Pause,
+    Resume,
+    MainMenu,
+    Quit,
+}
+
+#[derive(Resource)]
+pub struct MenuState {
+    pub screen: MenuScreen,
+    /// Where `Back` goes from the settings
+    pub back_to: MenuScreen,
+    pub game_started: bool,
+    /// The level a new game, or a restart, begins at
+    pub start_level: u32,
+    pub selected_level: u32,
+    pub highest_level: u32,
+}
+
+impl Default for MenuState {
+    fn default() -> Self {
+        Self {
+            screen: MenuScreen::Main,
+            back_to: MenuScreen::Main,
+            game_started: false,
+            start_level: 1,
+            selected_level: 1,
+            highest_level: 0,
+        }
+    }
+}
+
+const PROGRESS_FILE: &str = "progress";
+
+impl MenuState {
+    /// Picks up where the player got to last time, so `Continue` has something to continue
+    pub fn load() -> Self {
+        let highest_level = storage::read(PROGRESS_FILE)
+            .and_then(|text| {
+                text.lines()
+                    .filter_map(|line| line.split_once('='))
+                    .find(|(key, _)| key.trim() == "highest_level")
+                    .and_then(|(_, value)| value.trim().parse::<u32>().ok())
+            })
+            .unwrap_or(0)
+            .min(LAST_LEVEL);
+        Self { highest_level, ..default() }
+    }
+
+    fn save(&self) {
+        storage::write(PROGRESS_FILE, &format!("highest_level = {}\n", self.highest_level));
+    }
+}
+
+pub fn not_in_menu(menu: Res<MenuState>) -> bool {
+    menu.screen == MenuScreen::None
+}
+
+/// Stops and starts the game world, for the menus and photo mode alike.
+/// Nothing moves while paused, not even the physics.
+#[derive(SystemParam)]
+pub struct GamePause<'w, 's> {
+    virtual_time: ResMut<'w, Time<Virtual>>,
+    physics_loop: ResMut<'w, PhysicsLoop>,
+    santa_query: Query<'w, 's, &'static mut Controller, With<Santa>>,
+}
+
+impl GamePause<'_, '_> {
+    pub fn is_paused(&self) -> bool {
+        self.virtual_time.is_paused()
+    }
+
+    pub fn pause(&mut self) {
+        self.virtual_time.pause();
+        self.physics_loop.pause();
+    }
+
+    pub fn resume(&mut self) {
+        self.virtual_time.unpause();
+        self.physics_loop.resume();
+        // Keys let go of while paused never reached Santa
+        for mut controller in self.santa_query.iter_mut() {
+            controller.directions.clear();
+            controller.rotations.clear();
+            controller.triggers.clear();
+        }
+    }
+}
+
+/// The text the menus show, bound to the labels
+#[derive(Component, Default)]
+pub struct MenuModel {
+    pub level_text: String,
+    pub quality_text: String,
+    pub volume_text: String,
+    pub ui_scale_text: String,
+    pub screen_shake_text: String,
+    pub flashing_text: String,
+    pub gift_cam_text: String,
+}
+
+fn spawn_menu_model(mut commands: Commands) {
+    commands.add(ess! {
+        .menu {
+            position-type: absolute;
+            left: 0px;
+            top: 0px;
+            width: 100%;
+            height: 100%;
+            flex-direction: column;
+            align-items: center;
+            justify-content: center;
+            row-gap: 12px;
+            background-color: #000000bb;
+            color: white;
+            font-size: 24px;
+        }
+        .menu_title {
+            font: bold;
+            font-size: 48px;
+            margin-bottom: 24px;
+        }
+        .menu button {
+            width: 280px;
+            justify-content: center;
+        }
+        .menu_row {
+            column-gap: 12px;
+            align-items: center;
+        }
+        .menu_row button {
+            width: 60px;
+        }
+    });
+    commands.spawn((Name::from("Menu Model"), MenuModel::default()));
+}
+
+/// Escape pauses the game, backs out of sub menus and resumes from the pause menu
+fn menu_keys(
+    keys: Res<Input<KeyCode>>,
+    menu: Res<MenuState>,
+    mut menu_action_ew: EventWriter<MenuAction>,
+) {
+    if !keys.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    match menu.screen {
+        MenuScreen::None if menu.game_started => menu_action_ew.send(MenuAction::Pause),
+        MenuScreen::Pause => menu_action_ew.send(MenuAction::Resume),
+        MenuScreen::LevelSelect | MenuScreen::Settings => menu_action_ew.send(MenuAction::Back),
+        _ => {}
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_menu_actions(
+    mut commands: Commands,
+    mut menu_action_er: EventReader<MenuAction>,
+    mut menu: ResMut<MenuState>,
+    mut game_event_ew: EventWriter<GameEvent>,
+    mut app_exit_ew: EventWriter<AppExit>,
+    mut game_pause: GamePause,
+    mut settings: ResMut<Settings>,
+    mut game_tracker: ResMut<GameTracker>,
+    level_query: Query<Entity, Or<(With<SamSite>, With<House>, With<VillageCenter>, With<SurfaceToAirMissile>)>>,
+) {
+    for menu_action in menu_action_er.read() {
+        let mut start_level = None;
+        match menu_action {
+            MenuAction::Play => start_level = Some(1),
+            MenuAction::Continue => {
+                if menu.game_started {
+                    menu.screen = MenuScreen::None;
+                } else {
+                    start_level = Some(menu.highest_level.max(1));
+                }
+            }
+            MenuAction::LevelSelect => {
+                menu.selected_level = menu.highest_level.max(1);
+                menu.screen = MenuScreen::LevelSelect;
+            }
+            MenuAction::PreviousLevel => menu.selected_level = menu.selected_level.saturating_sub(1).max(1),
+            MenuAction::NextLevel => menu.selected_level = (menu.selected_level + 1).min(menu.highest_level.clamp(1, LAST_LEVEL)),
+            MenuAction::StartSelectedLevel => start_level = Some(menu.selected_level),
+            MenuAction::Settings => {
+                menu.back_to = menu.screen;
+                menu.screen = MenuScreen::Settings;
+            }
+            MenuAction::CycleQuality => {
+                let quality = settings.quality.next();
+                settings.set_quality(quality);
+            }
+            MenuAction::CycleVolume => {
+                settings.master_volume = if settings.master_volume >= 1.0 { 0.0 } else { (settings.master_volume + 0.25).min(1.0) };
+            }
+            MenuAction::CycleUiScale => {
+                settings.ui_scale = if settings.ui_scale >= 2.0 { 0.75 } else { settings.ui_scale + 0.25 };
+            }
+            MenuAction::ToggleScreenShake => settings.screen_shake = if settings.screen_shake > 0.0 { 0.0 } else { 1.0 },
+            MenuAction::ToggleFlashing => settings.flashing = !settings.flashing,
+            MenuAction::ToggleGiftCam => settings.gift_cam = !settings.gift_cam,
+            MenuAction::Back => {
+                menu.screen = if menu.screen == MenuScreen::Settings { menu.back_to } else { MenuScreen::Main };
+            }
+            MenuAction::Pause => menu.screen = MenuScreen::Pause,
+            MenuAction::Resume => menu.screen = MenuScreen::None,
+            MenuAction::MainMenu => menu.screen = MenuScreen::Main,
+            MenuAction::Quit => app_exit_ew.send(AppExit),
+        }
+
+        if let Some(level) = start_level {
+            menu.start_level = level;
+            menu.screen = MenuScreen::None;
+            if menu.game_started {
+                // Clear out whatever is left of the last game
+                for entity in level_query.iter() {
+                    commands.entity(entity).despawn_recursive();
+                }
+                game_tracker.score = 0;
+                game_event_ew.send(GameEvent { event_type: GameEventTypes::Restarted });
+            } else {
+                menu.game_started = true;
+                game_event_ew.send(GameEvent { event_type: GameEventTypes::Started });
+            }
+        }
+    }
+
+    if !menu.is_changed() {
+        return;
+    }
+    let paused = menu.screen != MenuScreen::None;
+    if paused && !game_pause.is_paused() {
+        game_pause.pause();
+    } else if !paused && game_pause.is_paused() {
+        game_pause.resume();
+    }
+}
+
+/// Level select only offers the levels that have been reached, saved as soon as a new one is
+fn track_levels(
+    mut load_level_er: EventReader<LoadLevel>,
+    mut menu: ResMut<MenuState>,
+) {
+    for load_level in load_level_er.read() {
+        if load_level.0 <= LAST_LEVEL && load_level.0 > menu.highest_level {
+            menu.highest_level = load_level.0;
+            menu.save();
+        }
+    }
+}
+
+fn update_menu_model(
+    menu: Res<MenuState>,
+    settings: Res<Settings>,
+    mut model_query: Query<&mut MenuModel>,
+) {
+    if !menu.is_changed() && !settings.is_changed() {
+        return;
+    }
+    for mut model in model_query.iter_mut() {
+        model.level_text = format!("Level {}", menu.selected_level);
+        model.quality_text = format!("Quality: {:?}", settings.quality);
+        model.volume_text = format!("Volume: {:.0}%", settings.master_volume * 100.0);
+        model.ui_scale_text = format!("UI scale: {:.0}%", settings.ui_scale * 100.0);
+        model.screen_shake_text = on_off("Screen shake", settings.screen_shake > 0.0);
+        model.flashing_text = on_off("Damage flash", settings.flashing);
+        model.gift_cam_text = on_off("Gift cam", settings.gift_cam);
+    }
+}
+
+fn on_off(name: &str, on: bool) -> String {
+    format!("{}: {}", name, if on { "on" } else { "off" })
+}
+
+/// Rebuilds the menu whenever the screen changes
+fn show_menu_screen(
+    mut elements: Elements,
+    menu: Res<MenuState>,
+    model_query: Query<Entity, With<MenuModel>>,
+    mut last_screen: Local<Option<MenuScreen>>,
+) {
+    if *last_screen == Some(menu.screen) {
+        return;
+    }
+    let Ok(model) = model_query.get_single() else {
+        return;
+    };
+    *last_screen = Some(menu.screen);
+    elements.select(".menu").remove();
+    match menu.screen {
+        MenuScreen::None => {}
+        MenuScreen::Main => {
+            elements.select("body").add_child(eml! {
+                <span c:menu>
+                    <label c:menu_title value="CHRISTMAS EVE"/>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Play))><label value="Play"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Continue))><label value="Continue"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::LevelSelect))><label value="Level Select"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Settings))><label value="Settings"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Quit))><label value="Quit"/></button>
+                </span>
+            });
+        }
+        MenuScreen::LevelSelect => {
+            elements.select("body").add_child(eml! {
+                <span c:menu>
+                    <label c:menu_title value="LEVEL SELECT"/>
+                    <span c:menu_row>
+                        <button on:press=run!(|ctx| ctx.send_event(MenuAction::PreviousLevel))><label value="<"/></button>
+                        <label bind:value=from!(model, MenuModel:level_text)/>
+                        <button on:press=run!(|ctx| ctx.send_event(MenuAction::NextLevel))><label value=">"/></button>
+                    </span>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::StartSelectedLevel))><label value="Start"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Back))><label value="Back"/></button>
+                </span>
+            });
+        }
+        MenuScreen::Settings => {
+            elements.select("body").add_child(eml! {
+                <span c:menu>
+                    <label c:menu_title value="SETTINGS"/>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::CycleQuality))><label bind:value=from!(model, MenuModel:quality_text)/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::CycleVolume))><label bind:value=from!(model, MenuModel:volume_text)/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::CycleUiScale))><label bind:value=from!(model, MenuModel:ui_scale_text)/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::ToggleScreenShake))><label bind:value=from!(model, MenuModel:screen_shake_text)/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::ToggleFlashing))><label bind:value=from!(model, MenuModel:flashing_text)/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::ToggleGiftCam))><label bind:value=from!(model, MenuModel:gift_cam_text)/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Back))><label value="Back"/></button>
+                </span>
+            });
+        }
+        MenuScreen::Pause => {
+            elements.select("body").add_child(eml! {
+                <span c:menu>
+                    <label c:menu_title value="PAUSED"/>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Resume))><label value="Resume"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Settings))><label value="Settings"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::MainMenu))><label value="Main Menu"/></button>
+                    <button on:press=run!(|ctx| ctx.send_event(MenuAction::Quit))><label value="Quit"/></button>
+                </span>
+            });
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use std::f32::consts::TAU;
+use bevy::app::{App, Plugin, PostUpdate, Startup, Update};
+use bevy::asset::{Asset, AssetApp, AssetEvent, AssetLoader, Assets, AssetServer, AsyncReadExt, Handle, LoadContext};
+use bevy::asset::io::Reader;
+use bevy::core::Name;
+use bevy::hierarchy::DespawnRecursiveExt;
+use bevy::log::info;
+use bevy::math::{Quat, Vec3, Vec4};
+use bevy::pbr::{AlphaMode, PbrBundle, StandardMaterial};
+use bevy::prelude::{Color, Commands, Component, default, Entity, EventReader, GlobalTransform, IntoSystemConfigs, Query, Res, resource_changed, ResMut, Resource, Time, Transform, TransformBundle, Visibility};
+use bevy::transform::TransformSystem;
+use bevy::reflect::TypePath;
+use bevy::utils::{BoxedFuture, HashMap};
+use serde::Deserialize;
+use bevy_turborand::{DelegatedRng, GlobalRng};
+use bevy_xpbd_3d::prelude::LinearVelocity;
+use crate::assets::SantasAssets;
+use crate::constants::{PARTICLE_COLOR_STEPS, PARTICLE_POOL_SIZE};
+
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<ParticlePresetFile>()
+            .init_asset_loader::<ParticlePresetLoader>()
+            .init_resource::<ParticlePresets>()
+            .init_resource::<ParticleMaterials>()
+            .init_resource::<ParticlePool>()
+            .add_systems(Startup, load_particle_presets)
+            .add_systems(
+                Update, (
+                    apply_particle_presets,
+                    build_particle_materials.run_if(resource_changed::<ParticlePresets>()),
+                    update_particles,
+                ).chain(),
+            )
+            // Emitters moved or spawned this frame have their global transform by now
+            .add_systems(PostUpdate, emit_particles.after(TransformSystem::TransformPropagate))
+        ;
+    }
+}
+
+/// Every kind of particle effect in the game, each one has an `EmitterPreset`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize)]
+pub enum ParticleEffect {
+    MissileTrail,
+    Explosion,
+    ChimneySmoke,
+    SleighSparkle,
+    SnowImpact,
+}
+
+/// Keyframes over the life of a particle, 0.0 is when it is born and 1.0 when it dies
+#[derive(Clone, Deserialize)]
+pub struct Curve(pub Vec<(f32, f32)>);
+
+impl Curve {
+    pub fn constant(value: f32) -> Self {
+        Self(vec![(0.0, value)])
+    }
+
+    pub fn sample(&self, t: f32) -> f32 {
+        let Some(&(first_t, first_value)) = self.0.first() else {
+            return 1.0;
+        };
+        if t <= first_t {
+            return first_value;
+        }
+        for keys in self.0.windows(2) {
+            let ((from_t, from), (to_t, to)) = (keys[0], keys[1]);
+            if t <= to_t {
+                let f = (t - from_t) / (to_t - from_t).max(f32::EPSILON);
+                return from + (to - from) * f;
+            }
+        }
+        self.0.last().unwrap().1
+    }
+}
+
+/// Same as `Curve` but for colours, the alpha is taken from the preset's alpha curve
+#[derive(Clone, Deserialize)]
+pub struct ColorCurve(pub Vec<(f32, Color)>);
+
+impl ColorCurve {
+    pub fn sample(&self, t: f32) -> Color {
+        let Some(&(first_t, first_color)) = self.0.first() else {
+            return Color::WHITE;
+        };
+        if t <= first_t {
+            return first_color;
+        }
+        for keys in self.0.windows(2) {
+            let ((from_t, from), (to_t, to)) = (keys[0], keys[1]);
+            if t <= to_t {
+                let f = (t - from_t) / (to_t - from_t).max(f32::EPSILON);
+                let color = Vec4::from(from.as_rgba_f32()).lerp(Vec4::from(to.as_rgba_f32()), f);
+                return Color::rgba(color.x, color.y, color.z, color.w);
+            }
+        }
+        self.0.last().unwrap().1
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+pub enum ParticleMesh {
+    /// The big soft sphere the missile trails always used
+    Puff,
+    /// The tiny snowball mesh
+    Flake,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct ParticleMaterial {
+    /// How much the particle glows in its own colour, 0.0 for smoke
+    pub emissive: f32,
+    pub unlit: bool,
+}
+
+/// Everything an emitter needs to know to spawn one kind of effect.
+/// Ranges are (min, max) and picked at random per particle.
+#[derive(Clone, Deserialize)]
+pub struct EmitterPreset {
+    /// Particles per second for as long as the emitter lives
+    pub spawn_rate: f32,
+    /// Particles spawned at once when the emitter starts
+    pub burst: (u32, u32),
+    pub lifetime: (f32, f32),
+    /// Radius of the particle, the size curve is multiplied with it
+    pub size: (f32, f32),
+    pub size_curve: Curve,
+    pub color: ColorCurve,
+    pub alpha: Curve,
+    /// Particles leave along this direction, spread out over a cone with this half angle
+    pub direction: Vec3,
+    pub cone_angle: f32,
+    pub speed: (f32, f32),
+    /// How much of the emitter's own velocity the particles keep
+    pub inherit_velocity: f32,
+    /// Gravity for sparks, buoyancy for smoke
+    pub acceleration: Vec3,
+    /// Particles start somewhere inside a ball this big around the emitter
+    pub spawn_radius: f32,
+    pub mesh: ParticleMesh,
+    pub material: ParticleMaterial,
+}
+
+/// The data for every effect. They are loaded from `assets/particles.ron`, tweak
+/// them there rather than in the systems. A copy of the file is built into the game,
+/// it is used until the file is in, and for any effect the file leaves out.
+#[derive(Resource)]
+pub struct ParticlePresets(pub HashMap<ParticleEffect, EmitterPreset>);
+
+const BUILT_IN_PRESETS: &str = include_str!("../assets/particles.ron");
+
+impl Default for ParticlePresets {
+    fn default() -> Self {
+        let presets: std::collections::HashMap<ParticleEffect, EmitterPreset> = ron::from_str(BUILT_IN_PRESETS)
+            .expect("the particle presets built into the game don't parse");
+        Self(presets.into_iter().collect())
+    }
+}
+
+/// The presets as read from disk
+#[derive(Asset, TypePath, Deserialize)]
+pub struct ParticlePresetFile(pub std::collections::HashMap<ParticleEffect, EmitterPreset>);
+
+#[derive(Default)]
+pub struct ParticlePresetLoader;
+
+impl AssetLoader for ParticlePresetLoader {
+    type Asset = ParticlePresetFile;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["particles.ron"]
+    }
+}
+
+/// Keeps the file loaded, and hot reloading when that is turned on
+#[derive(Resource)]
+pub struct ParticlePresetHandle(pub Handle<ParticlePresetFile>);
+
+fn load_particle_presets(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ParticlePresetHandle(asset_server.load("particles.ron")));
+}
+
+fn apply_particle_presets(
+    mut asset_events: EventReader<AssetEvent<ParticlePresetFile>>,
+    preset_files: Res<Assets<ParticlePresetFile>>,
+    mut presets: ResMut<ParticlePresets>,
+) {
+    for asset_event in asset_events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = asset_event else {
+            continue;
+        };
+        let Some(preset_file) = preset_files.get(*id) else {
+            continue;
+        };
+        let mut loaded = ParticlePresets::default();
+        for (effect, preset) in preset_file.0.iter() {
+            loaded.0.insert(*effect, preset.clone());
+        }
+        info!("Loaded {} particle presets", preset_file.0.len());
+        *presets = loaded;
+    }
+}
+
+/// Colour and alpha curves baked into a few materials per effect, particles
+/// swap between them as they age instead of each owning a material.
+#[derive(Resource, Default)]
+pub struct ParticleMaterials(pub HashMap<ParticleEffect, Vec<Handle<StandardMaterial>>>);
+
+impl ParticleMaterials {
+    pub fn step(t: f32) -> usize {
+        (t.clamp(0.0, 1.0) * (PARTICLE_COLOR_STEPS - 1) as f32).round() as usize
+    }
+
+    pub fn get(&self, effect: ParticleEffect, step: usize) -> Handle<StandardMaterial> {
+        self.0.get(&effect).and_then(|steps| steps.get(step)).cloned().unwrap_or_default()
+    }
+}
+
+/// Runs again whenever the presets change
+fn build_particle_materials(
+    presets: Res<ParticlePresets>,
+    mut particle_materials: ResMut<ParticleMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (effect, preset) in presets.0.iter() {
+        let steps = (0..PARTICLE_COLOR_STEPS)
+            .map(|step| {
+                let t = step as f32 / (PARTICLE_COLOR_STEPS - 1) as f32;
+                let color = preset.color.sample(t).with_a(preset.alpha.sample(t));
+                materials.add(StandardMaterial {
+                    base_color: color,
+                    emissive: color * preset.material.emissive,
+                    unlit: preset.material.unlit,
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                })
+            })
+            .collect();
+        particle_materials.0.insert(*effect, steps);
+    }
+}
+
+/// Spawns particles of one effect. Put it on anything with a transform, if the
+/// entity also has a `LinearVelocity` the particles can inherit some of it.
+#[derive(Component)]
+pub struct ParticleEmitter {
+    pub effect: ParticleEffect,
+    /// Leftover fraction of a particle from the last frame
+    pub accumulated: f32,
+    pub burst_pending: bool,
+    /// Despawn the emitter once the burst is out, for explosions and impacts
+    pub one_shot: bool,
+}
+
+impl ParticleEmitter {
+    pub fn new(effect: ParticleEffect) -> Self {
+        Self {
+            effect,
+            accumulated: 0.0,
+            burst_pending: true,
+            one_shot: false,
+        }
+    }
+
+    /// Waits for `fire` before bursting, for emitters that are reused
+    pub fn idle(effect: ParticleEffect) -> Self {
+        Self {
+            burst_pending: false,
+            ..Self::new(effect)
+        }
+    }
+
+    pub fn fire(&mut self) {
+        self.burst_pending = true;
+    }
+
+    pub fn burst(effect: ParticleEffect) -> Self {
+        Self {
+            one_shot: true,
+            ..Self::new(effect)
+        }
+    }
+
+    /// A throwaway emitter that fires its burst at `position` and is gone
+    pub fn burst_at(effect: ParticleEffect, position: Vec3) -> (Name, TransformBundle, Self) {
+        (
+            Name::from("Particle Burst"),
+            TransformBundle::from_transform(Transform::from_translation(position)),
+            Self::burst(effect),
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct Particle {
+    pub effect: ParticleEffect,
+    pub size: f32,
+    pub velocity: Vec3,
+    pub material_step: usize,
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+impl Particle {
+    /// How far through its life it is, 0.0 when born and 1.0 when it is done
+    pub fn fraction(&self) -> f32 {
+        if self.lifetime <= 0.0 {
+            return 1.0;
+        }
+        (self.age / self.lifetime).clamp(0.0, 1.0)
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.age < self.lifetime
+    }
+}
+
+/// Particles that are done are hidden and kept here for the next emitter,
+/// so a busy sky doesn't spawn and despawn hundreds of entities a second.
+/// Holds at most `PARTICLE_POOL_SIZE`, the rest go after a big fight.
+#[derive(Resource, Default)]
+pub struct ParticlePool {
+    pub free: Vec<Entity>,
+}
+
+fn range(global_rng: &mut GlobalRng, (min, max): (f32, f32)) -> f32 {
+    min + (max - min) * global_rng.f32()
+}
+
+/// A random direction at most `cone_angle` away from `direction`
+fn cone_direction(global_rng: &mut GlobalRng, direction: Vec3, cone_angle: f32) -> Vec3 {
+    let tilt = global_rng.f32() * cone_angle;
+    let spin = global_rng.f32() * TAU;
+    let local = Quat::from_rotation_y(spin) * Quat::from_rotation_x(tilt) * Vec3::Y;
+    Quat::from_rotation_arc(Vec3::Y, direction.normalize_or_zero()) * local
+}
+
+#[allow(clippy::too_many_arguments)]
+fn emit_particles(
+    mut commands: Commands,
+    mut emitters: Query<(Entity, &GlobalTransform, &mut ParticleEmitter, Option<&LinearVelocity>)>,
+    mut particle_pool: ResMut<ParticlePool>,
+    presets: Res<ParticlePresets>,
+    particle_materials: Res<ParticleMaterials>,
+    santas_assets: Res<SantasAssets>,
+    mut global_rng: ResMut<GlobalRng>,
+    time: Res<Time>,
+) {
+    for (entity, global_transform, mut emitter, linear_velocity) in emitters.iter_mut() {
+        let Some(preset) = presets.0.get(&emitter.effect) else {
+            continue;
+        };
+        let mut count = 0;
+        if emitter.burst_pending {
+            emitter.burst_pending = false;
+            count += global_rng.u32(preset.burst.0..=preset.burst.1);
+        }
+        if !emitter.one_shot {
+            emitter.accumulated += preset.spawn_rate * time.delta_seconds();
+            count += emitter.accumulated as u32;
+            emitter.accumulated = emitter.accumulated.fract();
+        }
+
+        let inherited_velocity = linear_velocity.map_or(Vec3::ZERO, |velocity| velocity.0) * preset.inherit_velocity;
+        let mesh = match preset.mesh {
+            ParticleMesh::Puff => santas_assets.trail_mesh.clone(),
+            ParticleMesh::Flake => santas_assets.snowball_mesh.clone(),
+        };
+        for _n in 0..count {
+            let offset = Vec3::new(global_rng.f32_normalized(), global_rng.f32_normalized(), global_rng.f32_normalized()) * preset.spawn_radius;
+            let particle = Particle {
+                effect: emitter.effect,
+                size: range(&mut global_rng, preset.size),
+                velocity: inherited_velocity + cone_direction(&mut global_rng, preset.direction, preset.cone_angle) * range(&mut global_rng, preset.speed),
+                material_step: 0,
+                age: 0.0,
+                lifetime: range(&mut global_rng, preset.lifetime),
+            };
+            let transform = Transform::from_translation(global_transform.translation() + offset)
+                .with_scale(Vec3::splat(particle.size * preset.size_curve.sample(0.0)));
+            // Propagation has already run this frame, so the global transform is set by hand
+            let bundle = PbrBundle {
+                mesh: mesh.clone(),
+                material: particle_materials.get(emitter.effect, 0),
+                transform,
+                global_transform: GlobalTransform::from(transform),
+                ..Default::default()
+            };
+            match particle_pool.free.pop() {
+                Some(pooled) => {
+                    commands.entity(pooled).insert((bundle, particle));
+                }
+                None => {
+                    commands.spawn((Name::from("Particle"), bundle, particle));
+                }
+            }
+        }
+        if emitter.one_shot {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn update_particles(
+    mut commands: Commands,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform, &mut Handle<StandardMaterial>, &mut Visibility)>,
+    mut particle_pool: ResMut<ParticlePool>,
+    presets: Res<ParticlePresets>,
+    particle_materials: Res<ParticleMaterials>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut particle, mut transform, mut material, mut visibility) in particles.iter_mut() {
+        if !particle.is_alive() {
+            continue;
+        }
+        particle.age += delta;
+        if !particle.is_alive() {
+            if particle_pool.free.len() < PARTICLE_POOL_SIZE {
+                *visibility = Visibility::Hidden;
+                particle_pool.free.push(entity);
+            } else {
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
+        }
+        let Some(preset) = presets.0.get(&particle.effect) else {
+            continue;
+        };
+        particle.velocity += preset.acceleration * delta;
+        transform.translation += particle.velocity * delta;
+
+        let t = particle.fraction();
+        transform.scale = Vec3::splat(particle.size * preset.size_curve.sample(t));
+        let step = ParticleMaterials::step(t);
+        if step != particle.material_step {
+            particle.material_step = step;
+            *material = particle_materials.get(particle.effect, step);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 0.0001;
+
+    #[test]
+    fn the_built_in_presets_cover_every_effect() {
+        let presets = ParticlePresets::default();
+        for effect in [ParticleEffect::MissileTrail, ParticleEffect::Explosion, ParticleEffect::ChimneySmoke, ParticleEffect::SleighSparkle, ParticleEffect::SnowImpact] {
+            assert!(presets.0.contains_key(&effect), "{:?}", effect);
+        }
+    }
+
+    #[test]
+    fn curves_interpolate_between_keys_and_hold_at_the_ends() {
+        let curve = Curve(vec![(0.2, 1.0), (0.5, 4.0), (1.0, 2.0)]);
+        let cases = [
+            (-1.0, 1.0),
+            (0.0, 1.0),
+            (0.2, 1.0),
+            (0.35, 2.5),
+            (0.5, 4.0),
+            (0.75, 3.0),
+            (1.0, 2.0),
+            (2.0, 2.0),
+        ];
+        for (t, expected) in cases {
+            assert!((curve.sample(t) - expected).abs() < TOLERANCE, "at {}: {}", t, curve.sample(t));
+        }
+        for t in [0.0, 0.5, 1.0] {
+            assert_eq!(Curve::constant(3.0).sample(t), 3.0);
+            assert_eq!(Curve(vec![]).sample(t), 1.0);
+        }
+        // Two keys at the same time jump instead of dividing by zero
+        let step = Curve(vec![(0.0, 0.0), (0.5, 0.0), (0.5, 1.0), (1.0, 1.0)]);
+        assert!(step.sample(0.5).is_finite());
+        assert_eq!(step.sample(0.75), 1.0);
+    }
+
+    #[test]
+    fn color_curves_interpolate_every_channel() {
+        let curve = ColorCurve(vec![(0.0, Color::rgba(1.0, 0.0, 0.0, 1.0)), (1.0, Color::rgba(0.0, 0.5, 1.0, 0.0))]);
+        let cases = [
+            (-0.5, [1.0, 0.0, 0.0, 1.0]),
+            (0.0, [1.0, 0.0, 0.0, 1.0]),
+            (0.5, [0.5, 0.25, 0.5, 0.5]),
+            (1.0, [0.0, 0.5, 1.0, 0.0]),
+            (1.5, [0.0, 0.5, 1.0, 0.0]),
+        ];
+        for (t, expected) in cases {
+            let color = Vec4::from(curve.sample(t).as_rgba_f32());
+            assert!(color.distance(Vec4::from(expected)) < TOLERANCE, "at {}: {}", t, color);
+        }
+        assert_eq!(ColorCurve(vec![]).sample(0.5), Color::WHITE);
+    }
+}
@@ -0,0 +1,211 @@
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::core::Name;
+use bevy::input::Input;
+use bevy::math::{EulerRot, Quat, Vec3};
+use bevy::pbr::{FogFalloff, FogSettings};
+use bevy::prelude::{Camera, Camera3dBundle, Commands, Component, default, Entity, IntoSystemConfigs, KeyCode, Node, Parent, PerspectiveProjection, Projection, Query, Real, Res, ResMut, Resource, Time, Transform, UiCameraConfig, Visibility, With, Without};
+use bevy::render::view::ColorGrading;
+use crate::camera::GameCamera;
+use crate::constants::{PHOTO_FLY_SPEED, PHOTO_TURN_SPEED};
+use crate::menus::{GamePause, not_in_menu};
+use crate::settings::Settings;
+use crate::weather::Weather;
+
+pub struct PhotoModePlugin;
+
+impl Plugin for PhotoModePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PhotoMode>()
+            .add_systems(Startup, spawn_photo_camera)
+            .add_systems(
+                Update, (
+                    toggle_photo_mode.run_if(not_in_menu),
+                    fly_photo_camera.run_if(in_photo_mode),
+                    adjust_photo_settings.run_if(in_photo_mode),
+                ).chain(),
+            )
+        ;
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PhotoMode {
+    pub active: bool,
+    /// The UI roots hidden for the shot and how they were before
+    hidden_ui: Vec<(Entity, Visibility)>,
+}
+
+pub fn in_photo_mode(photo_mode: Res<PhotoMode>) -> bool {
+    photo_mode.active
+}
+
+pub fn not_in_photo_mode(photo_mode: Res<PhotoMode>) -> bool {
+    !photo_mode.active
+}
+
+/// A camera of its own so the chase camera is left exactly where it was
+#[derive(Component)]
+pub struct PhotoCamera {
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Fog visibility in world units, starts out as whatever the weather had
+    pub visibility: f32,
+}
+
+fn spawn_photo_camera(mut commands: Commands) {
+    commands.spawn((
+        Name::from("Photo Camera"),
+        PhotoCamera {
+            yaw: 0.0,
+            pitch: 0.0,
+            visibility: 1500.0,
+        },
+        Camera3dBundle {
+            camera: Camera {
+                order: 2,
+                is_active: false,
+                ..default()
+            },
+            ..default()
+        },
+        FogSettings::default(),
+        UiCameraConfig { show_ui: false },
+    ));
+}
+
+/// The photo mode key freezes the game and hands over to the photo camera, and back again
+#[allow(clippy::too_many_arguments)]
+fn toggle_photo_mode(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<Settings>,
+    mut photo_mode: ResMut<PhotoMode>,
+    mut game_pause: GamePause,
+    mut game_camera_query: Query<(&mut Camera, &Transform, &Projection, &FogSettings, &ColorGrading), (With<GameCamera>, Without<PhotoCamera>)>,
+    mut photo_camera_query: Query<(&mut Camera, &mut Transform, &mut Projection, &mut FogSettings, &mut ColorGrading, &mut PhotoCamera), Without<GameCamera>>,
+    mut ui_root_query: Query<(Entity, &mut Visibility), (With<Node>, Without<Parent>)>,
+    weather: Res<Weather>,
+) {
+    if !keys.just_pressed(settings.bindings.photo_mode) {
+        return;
+    }
+    let Ok((mut game_camera, game_transform, game_projection, game_fog, game_color_grading)) = game_camera_query.get_single_mut() else {
+        return;
+    };
+    let Ok((mut photo_camera, mut photo_transform, mut photo_projection, mut photo_fog, mut photo_color_grading, mut photo)) = photo_camera_query.get_single_mut() else {
+        return;
+    };
+    photo_mode.active = !photo_mode.active;
+    if photo_mode.active {
+        game_pause.pause();
+        // Start the shot from wherever the game was looking
+        *photo_transform = *game_transform;
+        *photo_projection = game_projection.clone();
+        *photo_fog = game_fog.clone();
+        *photo_color_grading = *game_color_grading;
+        let (yaw, pitch, _) = game_transform.rotation.to_euler(EulerRot::YXZ);
+        photo.yaw = yaw;
+        photo.pitch = pitch;
+        photo.visibility = weather.visibility;
+        game_camera.is_active = false;
+        photo_camera.is_active = true;
+        // No HUD in the pictures. The UI is drawn by the default UI camera, not by
+        // this one, so `UiCameraConfig` alone doesn't keep it out.
+        for (entity, mut visibility) in ui_root_query.iter_mut() {
+            photo_mode.hidden_ui.push((entity, *visibility));
+            *visibility = Visibility::Hidden;
+        }
+    } else {
+        game_pause.resume();
+        game_camera.is_active = true;
+        photo_camera.is_active = false;
+        for (entity, was) in photo_mode.hidden_ui.drain(..) {
+            if let Ok((_, mut visibility)) = ui_root_query.get_mut(entity) {
+                *visibility = was;
+            }
+        }
+    }
+}
+
+/// Flies with Santa's keys, the photo up and down keys and the look keys are its own.
+/// Runs on real time, virtual time is paused.
+fn fly_photo_camera(
+    keys: Res<Input<KeyCode>>,
+    mut photo_camera_query: Query<(&mut Transform, &mut PhotoCamera)>,
+    real_time: Res<Time<Real>>,
+    settings: Res<Settings>,
+) {
+    let delta = real_time.delta_seconds();
+    let bindings = &settings.bindings;
+    let turn_speed = PHOTO_TURN_SPEED * settings.camera_sensitivity;
+    for (mut transform, mut photo) in photo_camera_query.iter_mut() {
+        if keys.pressed(bindings.photo_look_left) {
+            photo.yaw += turn_speed * delta;
+        }
+        if keys.pressed(bindings.photo_look_right) {
+            photo.yaw -= turn_speed * delta;
+        }
+        if keys.pressed(bindings.photo_look_up) {
+            photo.pitch += turn_speed * delta;
+        }
+        if keys.pressed(bindings.photo_look_down) {
+            photo.pitch -= turn_speed * delta;
+        }
+        photo.pitch = photo.pitch.clamp(-1.5, 1.5);
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, photo.yaw, photo.pitch, 0.0);
+
+        let mut movement = Vec3::ZERO;
+        if keys.pressed(bindings.forward) {
+            movement += transform.forward();
+        }
+        if keys.pressed(bindings.backward) {
+            movement -= transform.forward();
+        }
+        if keys.pressed(bindings.turn_right) {
+            movement += transform.right();
+        }
+        if keys.pressed(bindings.turn_left) {
+            movement -= transform.right();
+        }
+        if keys.pressed(bindings.photo_up) {
+            movement += Vec3::Y;
+        }
+        if keys.pressed(bindings.photo_down) {
+            movement -= Vec3::Y;
+        }
+        let speed = if keys.pressed(bindings.photo_fast) { PHOTO_FLY_SPEED * 4.0 } else { PHOTO_FLY_SPEED };
+        transform.translation += movement.normalize_or_zero() * speed * delta;
+    }
+}
+
+/// Zoom, fog and exposure, `[` `]`, `-` `=` and `,` `.` unless they were rebound
+fn adjust_photo_settings(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<Settings>,
+    mut photo_camera_query: Query<(&mut Projection, &mut FogSettings, &mut ColorGrading, &mut PhotoCamera)>,
+    real_time: Res<Time<Real>>,
+) {
+    let delta = real_time.delta_seconds();
+    let axis = |less: KeyCode, more: KeyCode| -> f32 {
+        match (keys.pressed(less), keys.pressed(more)) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            _ => 0.0,
+        }
+    };
+    let bindings = &settings.bindings;
+    let fov_change = axis(bindings.photo_zoom_in, bindings.photo_zoom_out);
+    let fog_change = axis(bindings.photo_fog_thicker, bindings.photo_fog_thinner);
+    let exposure_change = axis(bindings.photo_darker, bindings.photo_brighter);
+    if fov_change == 0.0 && fog_change == 0.0 && exposure_change == 0.0 {
+        return;
+    }
+    for (mut projection, mut fog, mut color_grading, mut photo) in photo_camera_query.iter_mut() {
+        if let Projection::Perspective(PerspectiveProjection { fov, .. }) = projection.as_mut() {
+            *fov = (*fov + fov_change * delta).clamp(10.0_f32.to_radians(), 120.0_f32.to_radians());
+        }
+        photo.visibility = (photo.visibility * (1.0 + fog_change * delta)).clamp(50.0, 10000.0);
+        fog.falloff = FogFalloff::from_visibility(photo.visibility);
+        color_grading.exposure = (color_grading.exposure + exposure_change * delta).clamp(-4.0, 4.0);
+    }
+}
@@ -0,0 +1,303 @@
+use belly::build::{eml, FromWorldAndParams, widget, WidgetContext};
+use belly::core::eml::Params;
+use belly::prelude::*;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use crate::constants::{RADAR_RANGES, RADAR_SIZE};
+use crate::input::{ControlCommands, Controller};
+use crate::sam_site::{SamSite, SamTarget, SurfaceToAirMissile};
+use crate::santa::Santa;
+use crate::villages::{Disposition, House, NeedsGifts, VillageCenter};
+
+pub struct RadarPlugin;
+
+impl Plugin for RadarPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(PostStartup, spawn_radar)
+            .add_systems(
+                Update, (
+                    radar_controls,
+                    add_radar_blips,
+                    radar_blip_system,
+                ).chain(),
+            )
+        ;
+    }
+}
+
+/// The radar panel, centred on Santa with his heading pointing up
+#[derive(Component)]
+pub struct Radar {
+    /// Index into `RADAR_RANGES`
+    pub range_index: usize,
+    pub full_screen: bool,
+}
+
+impl Radar {
+    pub fn range(&self) -> f32 {
+        RADAR_RANGES[self.range_index % RADAR_RANGES.len()]
+    }
+}
+
+#[widget]
+#[param(range_index: usize => Radar: range_index)]
+fn radar(ctx: &mut WidgetContext) {
+    let content = ctx.content();
+    ctx.render(eml! {
+        <span s:left=managed() s:bottom=managed() s:width=managed() s:height=managed() s:position-type="absolute">
+            {content}
+        </span>
+    })
+}
+
+impl FromWorldAndParams for Radar {
+    fn from_world_and_params(_: &mut World, params: &mut Params) -> Self {
+        Radar {
+            range_index: params.try_get("range_index").unwrap_or(1),
+            full_screen: false,
+        }
+    }
+}
+
+/// One dot on the radar, the colour says what it is
+#[derive(Component)]
+pub struct RadarBlip {
+    pub target: Entity,
+}
+
+#[widget]
+#[param(target: Entity => RadarBlip: target)]
+fn blip(ctx: &mut WidgetContext) {
+    ctx.render(eml! {
+        <span s:left=managed() s:top=managed() s:display=managed() s:position-type="absolute" c:blip/>
+    })
+}
+
+impl FromWorldAndParams for RadarBlip {
+    fn from_world_and_params(_: &mut World, params: &mut Params) -> Self {
+        RadarBlip {
+            target: params.try_get("target").expect("Missing required `target` param")
+        }
+    }
+}
+
+fn spawn_radar(mut commands: Commands, mut elements: Elements) {
+    commands.add(ess! {
+        .radar {
+            background-color: #00000088;
+        }
+        .blip {
+            width: 6px;
+            height: 6px;
+        }
+    });
+    let range_index = 1_usize;
+    elements.select("body").add_child(eml! {
+        <radar range_index=range_index c:radar/>
+    });
+}
+
+/// `R` cycles the range, `M` blows the radar up into a full screen map
+fn radar_controls(
+    mut santa_query: Query<&mut Controller, With<Santa>>,
+    mut radar_query: Query<(&mut Radar, &mut Style)>,
+) {
+    for mut controller in santa_query.iter_mut() {
+        let cycle_range = controller.triggers.remove(&ControlCommands::CycleRadarRange);
+        let toggle_map = controller.triggers.remove(&ControlCommands::ToggleMap);
+        for (mut radar, mut style) in radar_query.iter_mut() {
+            if cycle_range {
+                radar.range_index = (radar.range_index + 1) % RADAR_RANGES.len();
+            }
+            if toggle_map {
+                radar.full_screen = !radar.full_screen;
+            }
+            // Also sets the size the first time round
+            if cycle_range || toggle_map || style.width == Val::Auto {
+                if radar.full_screen {
+                    style.left = Val::Px(0.0);
+                    style.bottom = Val::Px(0.0);
+                    style.width = Val::Percent(100.0);
+                    style.height = Val::Percent(100.0);
+                } else {
+                    style.left = Val::Px(12.0);
+                    style.bottom = Val::Px(12.0);
+                    style.width = Val::Px(RADAR_SIZE);
+                    style.height = Val::Px(RADAR_SIZE);
+                }
+            }
+        }
+    }
+}
+
+/// Everything the radar cares about gets a blip as soon as it shows up
+fn add_radar_blips(
+    mut elements: Elements,
+    new_targets: Query<Entity, Or<(Added<House>, Added<SamSite>, Added<VillageCenter>)>>,
+    new_missiles: Query<(Entity, &SamTarget), Added<SurfaceToAirMissile>>,
+    santa_query: Query<Entity, With<Santa>>,
+) {
+    let santa_entity = santa_query.get_single().ok();
+    let incoming = new_missiles
+        .iter()
+        .filter(|(_, target)| Some(target.0) == santa_entity)
+        .map(|(entity, _)| entity);
+    for target in new_targets.iter().chain(incoming) {
+        elements.select(".radar").add_child(eml! {
+            <blip target=target/>
+        });
+    }
+}
+
+/// Where on the radar a world position ends up, in pixels from the centre with up being
+/// Santa's heading. Anything out of range is pinned to the rim, the flag says whether it
+/// is really in range.
+pub fn radar_position(santa: &Transform, target: Vec3, range: f32, radius: f32) -> (Vec2, bool) {
+    let offset = santa.rotation.inverse().mul_vec3(target - santa.translation);
+    // Santa flies along his local -Z with +X on his right, which is exactly screen up and right
+    let flat = Vec2::new(offset.x, offset.z) / range * radius;
+    let in_range = flat.length() <= radius;
+    (flat.clamp_length_max(radius), in_range)
+}
+
+/// What a blip stands for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlipKind {
+    House {
+        disposition: Disposition,
+        needs_gifts: bool,
+    },
+    SamSite,
+    Village,
+    /// Only missiles coming for Santa have a blip
+    Missile,
+}
+
+impl BlipKind {
+    /// None for houses there is nothing more to do at
+    pub fn color(&self) -> Option<Color> {
+        match self {
+            BlipKind::House { needs_gifts: true, .. } => Some(Color::RED),
+            BlipKind::House { disposition: Disposition::Nice, needs_gifts: false } => Some(Color::GREEN),
+            BlipKind::House { disposition: Disposition::Naughty | Disposition::Empty, needs_gifts: false } => None,
+            BlipKind::SamSite => Some(Color::ORANGE),
+            BlipKind::Village => Some(Color::WHITE),
+            BlipKind::Missile => Some(Color::YELLOW),
+        }
+    }
+
+    /// Villages far away stay on the rim so there is always a way to the next one
+    pub fn stays_on_rim(&self) -> bool {
+        *self == BlipKind::Village
+    }
+}
+
+#[derive(SystemParam)]
+pub struct BlipTargets<'w, 's> {
+    houses: Query<'w, 's, (&'static Disposition, Has<NeedsGifts>), With<House>>,
+    sam_sites: Query<'w, 's, (), With<SamSite>>,
+    villages: Query<'w, 's, (), With<VillageCenter>>,
+    missiles: Query<'w, 's, (), With<SurfaceToAirMissile>>,
+}
+
+impl BlipTargets<'_, '_> {
+    pub fn kind(&self, target: Entity) -> Option<BlipKind> {
+        if let Ok((disposition, needs_gifts)) = self.houses.get(target) {
+            Some(BlipKind::House { disposition: *disposition, needs_gifts })
+        } else if self.sam_sites.contains(target) {
+            Some(BlipKind::SamSite)
+        } else if self.villages.contains(target) {
+            Some(BlipKind::Village)
+        } else if self.missiles.contains(target) {
+            Some(BlipKind::Missile)
+        } else {
+            None
+        }
+    }
+}
+
+fn radar_blip_system(
+    mut commands: Commands,
+    radar_query: Query<(&Radar, &Node)>,
+    mut blips: Query<(Entity, &RadarBlip, &mut Style, &mut BackgroundColor, &Node)>,
+    transforms: Query<&GlobalTransform>,
+    santa_query: Query<&Transform, With<Santa>>,
+    blip_targets: BlipTargets,
+) {
+    let (Ok((radar, radar_node)), Ok(santa_transform)) = (radar_query.get_single(), santa_query.get_single()) else {
+        return;
+    };
+    let center = radar_node.size() / 2.0;
+    let radius = center.min_element();
+    for (entity, blip, mut style, mut background_color, node) in blips.iter_mut() {
+        let Ok(target_transform) = transforms.get(blip.target) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let (position, in_range) = radar_position(santa_transform, target_transform.translation(), radar.range(), radius);
+        let Some(kind) = blip_targets.kind(blip.target) else {
+            // Whatever it was, it isn't anything the radar knows about any more
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let color = kind.color().filter(|_| in_range || kind.stays_on_rim());
+        let Some(color) = color else {
+            style.display = Display::None;
+            continue;
+        };
+        style.display = Display::Flex;
+        style.left = Val::Px((center.x + position.x - 0.5 * node.size().x).round());
+        style.top = Val::Px((center.y + position.y - 0.5 * node.size().y).round());
+        background_color.0 = color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 0.001;
+
+    #[test]
+    fn heading_is_up_and_right_is_right() {
+        for (santa_rotation, world) in [(0.0, Quat::IDENTITY), (90.0, Quat::from_rotation_y(90.0_f32.to_radians()))] {
+            let santa = Transform::from_xyz(50.0, 10.0, -20.0).with_rotation(world);
+            let cases = [
+                (Vec3::NEG_Z, Vec2::new(0.0, -50.0)),
+                (Vec3::Z, Vec2::new(0.0, 50.0)),
+                (Vec3::X, Vec2::new(50.0, 0.0)),
+                (Vec3::NEG_X, Vec2::new(-50.0, 0.0)),
+            ];
+            for (local_direction, expected) in cases {
+                let target = santa.translation + world * local_direction * 100.0 + Vec3::Y * 30.0;
+                let (position, in_range) = radar_position(&santa, target, 200.0, 100.0);
+                assert!(in_range, "rotated {}: {}", santa_rotation, local_direction);
+                assert!(position.distance(expected) < TOLERANCE, "rotated {}: {} ended up at {}", santa_rotation, local_direction, position);
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_is_pinned_to_the_rim() {
+        let santa = Transform::IDENTITY;
+        let (position, in_range) = radar_position(&santa, Vec3::new(0.0, 0.0, -200.0), 200.0, 100.0);
+        assert!(in_range);
+        assert!(position.distance(Vec2::new(0.0, -100.0)) < TOLERANCE);
+        let (position, in_range) = radar_position(&santa, Vec3::new(300.0, 0.0, -400.0), 200.0, 100.0);
+        assert!(!in_range);
+        assert!((position.length() - 100.0).abs() < TOLERANCE);
+        assert!(position.normalize().distance(Vec2::new(0.6, -0.8)) < TOLERANCE);
+    }
+
+    #[test]
+    fn done_houses_drop_off_the_radar() {
+        use Disposition::*;
+        for disposition in [Nice, Naughty, Empty] {
+            assert_eq!(BlipKind::House { disposition, needs_gifts: true }.color(), Some(Color::RED));
+        }
+        assert_eq!(BlipKind::House { disposition: Nice, needs_gifts: false }.color(), Some(Color::GREEN));
+        assert_eq!(BlipKind::House { disposition: Naughty, needs_gifts: false }.color(), None);
+        assert_eq!(BlipKind::House { disposition: Empty, needs_gifts: false }.color(), None);
+    }
+}
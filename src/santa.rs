@@ -1,18 +1,21 @@
 use bevy::app::{App, Plugin, PostStartup, Update};
 use bevy::core::Name;
 use bevy::hierarchy::{BuildChildren, Children};
-use bevy::math::{EulerRot, Quat, Vec3, vec3};
+use bevy::math::{EulerRot, Quat, Vec2, Vec3, vec3};
 use bevy::pbr::{SpotLight, SpotLightBundle};
-use bevy::prelude::{Color, Commands, Component, Entity, Event, EventReader, EventWriter, GlobalTransform, Query, Res, Time, Transform, With, Without};
+use bevy::prelude::{Color, Commands, Component, Entity, Event, EventReader, EventWriter, GlobalTransform, Has, Query, Res, Time, Transform, Visibility, With, Without};
 use bevy::scene::SceneBundle;
 use bevy::utils::default;
 use bevy_xpbd_3d::components::{AngularDamping, Collider, CollisionLayers, Friction, LinearDamping, LinearVelocity, RigidBody};
 use bevy_xpbd_3d::prelude::PhysicsLayer;
 use crate::assets::SantasAssets;
-use crate::constants::{GROUND_PLANE, SAM_ACCELERATION, SAM_MAX_SPEED, SAM_TIME_TO_LIVE, SANTA_ACCELERATION, SANTA_MAX_SPEED, SANTA_MISSILE_RANGE, SANTA_TURN_SPEED};
-use crate::input::{Controller, CoolDown, KeyboardController, KinematicMovement};
-use crate::sam_site::{MissileTrailEmitter, SamTarget, SpawnSamSiteAt, SurfaceToAirMissile};
-use crate::villages::{House, HouseEvent, HouseEventType, LoadLevel, NeedsGifts};
+use crate::constants::{CHIMNEY_DROP_DISTANCE, GROUND_PLANE, NOSE_SCAN_ANGLE, NOSE_SCAN_RANGE, SAM_ACCELERATION, SAM_MAX_SPEED, SAM_TIME_TO_LIVE, SANTA_ACCELERATION, SANTA_MAX_SPEED, SANTA_MISSILE_RANGE, SANTA_TURN_SPEED};
+use crate::input::{ControlCommands, Controller, CoolDown, KeyboardController, KinematicMovement};
+use crate::lifetime::Lifetime;
+use crate::particles::{ParticleEffect, ParticleEmitter};
+use crate::sam_site::{SamTarget, SpawnSamSiteAt, SurfaceToAirMissile};
+use crate::villages::{GameTracker, HitZone, HitZoneType, House, LoadLevel, NaughtyMarker, NeedsCoal, NeedsGifts, Revealed, VillageCenter};
+use crate::weather::WindDrift;
 
 pub struct SantaPlugin;
 
@@ -28,8 +31,10 @@ impl Plugin for SantaPlugin {
             .add_systems(
                 Update, (
                     fix_model_transforms,
+                    switch_payload,
                     search_for_targets,
                     track_target,
+                    scan_with_rudolphs_nose,
                     toggle_santa_shooting,
                     shoot_gifts_at_target,
                     is_santa_dead,
@@ -82,6 +87,16 @@ pub struct SantaChild;
 #[derive(Component)]
 pub struct SantaNeedsTarget;
 
+/// What Santa drops on a house
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Payload {
+    Gift,
+    Coal,
+}
+
+#[derive(Component)]
+pub struct SelectedPayload(pub Payload);
+
 #[derive(Component)]
 pub struct SantaHasTarget {
     pub target: Entity,
@@ -112,6 +127,10 @@ pub struct SantaStats {
     pub current_level: u32,
     pub houses_left: u32,
     pub sam_sites: u32,
+    pub score: u32,
+    pub christmas_spirit: i32,
+    pub clock: String,
+    pub level_time_left: String,
 }
 
 impl SantaStats {
@@ -121,6 +140,10 @@ impl SantaStats {
             current_level: 0,
             houses_left: 0,
             sam_sites: 0,
+            score: 0,
+            christmas_spirit: 100,
+            clock: String::new(),
+            level_time_left: String::new(),
         }
     }
 }
@@ -128,24 +151,22 @@ impl SantaStats {
 fn update_santa_stats(
     mut santa_query: Query<&mut SantaStats, With<Santa>>,
     mut load_level_er: EventReader<LoadLevel>,
-    mut house_er: EventReader<HouseEvent>,
-    mut spawn_sam_sites: EventReader<SpawnSamSiteAt>
+    village_query: Query<&VillageCenter>,
+    mut spawn_sam_sites: EventReader<SpawnSamSiteAt>,
+    game_tracker: Res<GameTracker>,
 ) {
     for mut santa_stats in santa_query.iter_mut() {
+        santa_stats.score = game_tracker.score;
         for load_level in load_level_er.read() {
             santa_stats.current_level = load_level.0;
             santa_stats.sam_sites = 0;
-            santa_stats.houses_left = load_level.0 * 2 + load_level.0;
-        }
-        for house_event in house_er.read() {
-            match house_event.0 {
-                HouseEventType::ReceivedGifts(_) => {
-                    if santa_stats.houses_left > 0 {
-                        santa_stats.houses_left -= 1;
-                    }
-                }
-            }
         }
+        let current_level = santa_stats.current_level;
+        santa_stats.houses_left = village_query
+            .iter()
+            .filter(|village| village.level == current_level && !village.is_bonus)
+            .map(|village| village.needs_gifts_count.max(0) as u32)
+            .sum();
         for _ in spawn_sam_sites.read() {
             santa_stats.sam_sites += 1;
         }
@@ -160,7 +181,6 @@ pub struct RudolphsRedNose {
 fn spawn_santa(
     mut commands: Commands,
     santas_assets: Res<SantasAssets>,
-    mut game_event_ew: EventWriter<GameEvent>
 ) {
     let santa_entity = commands.spawn((
         Name::from("Saint Nicholas"),
@@ -186,6 +206,8 @@ fn spawn_santa(
         LinearDamping(0.9),
         RigidBody::Kinematic,
         SantaNeedsTarget,
+        SelectedPayload(Payload::Gift),
+        ParticleEmitter::new(ParticleEffect::SleighSparkle),
         CollisionLayers::new(
             [CollisionLayer::Santa],
             [
@@ -216,15 +238,15 @@ fn spawn_santa(
                     range: 2000.0,
                     radius: 0.0,
                     shadows_enabled: true,
-                    inner_angle: std::f32::consts::FRAC_PI_8 / 16.0,
-                    outer_angle: std::f32::consts::FRAC_PI_8 / 8.0,
+                    // Wide enough to light up a rooftop now that the nights are dark
+                    inner_angle: std::f32::consts::FRAC_PI_8 / 8.0,
+                    outer_angle: std::f32::consts::FRAC_PI_8 / 2.0,
                     ..default()
                 },
                 transform: Transform::from_xyz(0.0, 0.0, 0.5).looking_at(Vec3::new(0.0, -1.0, 10.0), Vec3::Y),
                 ..Default::default()
             },
         ));
-    game_event_ew.send(GameEvent { event_type: GameEventTypes::Started });
 }
 
 pub fn fix_model_transforms(
@@ -255,14 +277,22 @@ pub enum TargetEventTypes {
 pub struct TargetEvent(pub TargetEventTypes);
 
 fn search_for_targets(
-    house_query: Query<(Entity, &Transform, &House), With<NeedsGifts>>,
-    santas_position: Query<(Entity, &GlobalTransform), With<SantaNeedsTarget>>,
+    house_query: Query<(Entity, &Transform, &House, Has<NeedsGifts>, Has<NeedsCoal>, Has<Revealed>)>,
+    santas_position: Query<(Entity, &GlobalTransform, &SelectedPayload), With<SantaNeedsTarget>>,
     mut commands: Commands,
     mut target_ew: EventWriter<TargetEvent>,
 ) {
-    if let Ok((santa_entity, santas_position)) = santas_position.get_single() {
+    if let Ok((santa_entity, santas_position, selected_payload)) = santas_position.get_single() {
         let mut closest_house: Option<(Entity, f32)> = None;
-        for (house_entity, house_transform, _house) in house_query.iter() {
+        for (house_entity, house_transform, _house, needs_gifts, needs_coal, revealed) in house_query.iter() {
+            // Naughty houses can only be targeted once Rudolph has sniffed them out
+            let wants_payload = match selected_payload.0 {
+                Payload::Gift => needs_gifts,
+                Payload::Coal => needs_coal && revealed,
+            };
+            if !wants_payload {
+                continue;
+            }
             if closest_house.is_none() {
                 closest_house = Some((house_entity, house_transform.translation.distance(santas_position.translation())));
             } else {
@@ -282,13 +312,20 @@ fn search_for_targets(
 
 fn track_target(
     mut rudolphs_nose: Query<(&mut Transform, &RudolphsRedNose)>,
-    mut santa_query: Query<(Entity, &SantaHasTarget, &GlobalTransform), With<Santa>>,
-    target_query: Query<&GlobalTransform, (With<NeedsGifts>, Without<RudolphsRedNose>)>,
+    mut santa_query: Query<(Entity, &SantaHasTarget, &SelectedPayload, &GlobalTransform), With<Santa>>,
+    target_query: Query<(&GlobalTransform, Has<NeedsGifts>, Has<NeedsCoal>), Without<RudolphsRedNose>>,
     mut commands: Commands,
     mut target_ew: EventWriter<TargetEvent>,
 ) {
-    for (santa_entity, santa_has_target, santa_global) in santa_query.iter_mut() {
-        if let Ok(target_position) = target_query.get(santa_has_target.target) {
+    for (santa_entity, santa_has_target, selected_payload, santa_global) in santa_query.iter_mut() {
+        let target = target_query
+            .get(santa_has_target.target)
+            .ok()
+            .filter(|(_, needs_gifts, needs_coal)| match selected_payload.0 {
+                Payload::Gift => *needs_gifts,
+                Payload::Coal => *needs_coal,
+            });
+        if let Some((target_position, _, _)) = target {
             for (mut rudolph_local, _) in rudolphs_nose.iter_mut() {
                 rudolph_local.translation = santa_global.translation() + vec3(0.0, 0.0, 0.5);
 
@@ -311,6 +348,60 @@ fn track_target(
     }
 }
 
+fn switch_payload(
+    mut santa_query: Query<(Entity, &mut Controller, &mut SelectedPayload), With<Santa>>,
+    mut commands: Commands,
+    mut target_ew: EventWriter<TargetEvent>,
+) {
+    for (santa_entity, mut controller, mut selected_payload) in santa_query.iter_mut() {
+        if controller.triggers.remove(&ControlCommands::SwitchPayload) {
+            selected_payload.0 = match selected_payload.0 {
+                Payload::Gift => Payload::Coal,
+                Payload::Coal => Payload::Gift,
+            };
+            // The current target probably wants the other thing
+            commands.entity(santa_entity).remove::<SantaHasTarget>();
+            commands.entity(santa_entity).insert(SantaNeedsTarget);
+            target_ew.send(TargetEvent(TargetEventTypes::Lost));
+        }
+    }
+}
+
+/// Naughty houses look just like empty ones in the dark, but Rudolph's nose can tell them apart.
+/// Without a target the nose sweeps ahead of the sleigh.
+fn scan_with_rudolphs_nose(
+    mut rudolphs_nose: Query<(&mut Transform, &GlobalTransform), With<RudolphsRedNose>>,
+    santa_query: Query<(&GlobalTransform, Has<SantaHasTarget>), With<Santa>>,
+    naughty_houses: Query<(Entity, &GlobalTransform, &Children), (With<NeedsCoal>, Without<Revealed>)>,
+    mut marker_query: Query<&mut Visibility, With<NaughtyMarker>>,
+    mut commands: Commands,
+) {
+    let Ok((santa_global, has_target)) = santa_query.get_single() else {
+        return;
+    };
+    for (mut nose_transform, nose_global) in rudolphs_nose.iter_mut() {
+        if !has_target {
+            nose_transform.translation = santa_global.translation() + vec3(0.0, 0.0, 0.5);
+            let ahead = nose_transform.translation + santa_global.forward() * 50.0;
+            nose_transform.look_at(vec3(ahead.x, GROUND_PLANE, ahead.z), Vec3::Y);
+        }
+        let nose_position = nose_global.translation();
+        let nose_direction = nose_global.forward();
+        for (house_entity, house_global, children) in naughty_houses.iter() {
+            let to_house = house_global.translation() - nose_position;
+            if to_house.length() > NOSE_SCAN_RANGE || to_house.angle_between(nose_direction) > NOSE_SCAN_ANGLE {
+                continue;
+            }
+            commands.entity(house_entity).insert(Revealed);
+            for child in children.iter() {
+                if let Ok(mut visibility) = marker_query.get_mut(*child) {
+                    *visibility = Visibility::Visible;
+                }
+            }
+        }
+    }
+}
+
 fn toggle_santa_shooting(
     mut target_er: EventReader<TargetEvent>,
     mut santa_query: Query<&mut SantaHasTarget, With<Santa>>,
@@ -336,18 +427,34 @@ fn toggle_santa_shooting(
 pub struct GiftChild;
 
 fn shoot_gifts_at_target(
-    mut santa_query: Query<(Entity, &mut SantaHasTarget, &GlobalTransform), With<Santa>>,
+    mut santa_query: Query<(Entity, &mut SantaHasTarget, &SelectedPayload, &GlobalTransform), With<Santa>>,
+    house_query: Query<(&GlobalTransform, &Children), With<House>>,
+    hit_zone_query: Query<&HitZone>,
     mut commands: Commands,
     santas_assets: Res<SantasAssets>,
     time: Res<Time>,
 
 ) {
-    for (_santa_entity, mut santa_has_target, global_transform) in santa_query.iter_mut() {
+    for (_santa_entity, mut santa_has_target, selected_payload, global_transform) in santa_query.iter_mut() {
         if santa_has_target.is_shooting && santa_has_target.cool_down(time.delta_seconds()) {
-            let target_entity = santa_has_target.target;
-
             let santas_position = global_transform.translation();
 
+            // Right above the house Santa can drop the gift down the chimney
+            let mut target_entity = santa_has_target.target;
+            if let Ok((house_transform, children)) = house_query.get(target_entity) {
+                let to_house = house_transform.translation() - santas_position;
+                let horizontal_distance = Vec2::new(to_house.x, to_house.z).length();
+                if horizontal_distance < CHIMNEY_DROP_DISTANCE {
+                    // The hit zones are children of the house
+                    if let Some(chimney) = children
+                        .iter()
+                        .copied()
+                        .find(|child| hit_zone_query.get(*child).map_or(false, |zone| zone.zone_type == HitZoneType::Chimney)) {
+                        target_entity = chimney;
+                    }
+                }
+            }
+
             let missile_direction = Vec3::Z;
             let mut t = Transform::from_xyz(
                 santas_position.x,
@@ -360,14 +467,16 @@ fn shoot_gifts_at_target(
             commands
                 .spawn((
                     Name::from("Air2Surface, Bro!"),
-                    SurfaceToAirMissile::new(SAM_TIME_TO_LIVE, SAM_ACCELERATION * 5.0, 30.0, SAM_MAX_SPEED * 3.0),
+                    SurfaceToAirMissile::new(SAM_ACCELERATION * 5.0, 30.0, SAM_MAX_SPEED * 3.0),
+                    Lifetime::new(SAM_TIME_TO_LIVE),
                     SamTarget(target_entity),
+                    selected_payload.0,
                     SceneBundle {
                         scene: santas_assets.missile.clone(),
                         transform: t,
                         ..Default::default()
                     },
-                    MissileTrailEmitter::new(0.02),
+                    ParticleEmitter::new(ParticleEffect::MissileTrail),
                     RigidBody::Kinematic,
                     CollisionLayers::new(
                         [CollisionLayer::Gift],
@@ -375,6 +484,7 @@ fn shoot_gifts_at_target(
                             CollisionLayer::House,
                         ]),
                     LinearVelocity::from(missile_velocity),
+                    WindDrift::default(),
                 )).with_children(|children|
                 { // Spawn the child colliders positioned relative to the rigid body
                     children.spawn((
@@ -394,6 +504,8 @@ pub struct GameEvent {
 
 pub enum GameEventTypes {
     Lost,
+    /// Dawn came before all the presents were delivered
+    OutOfTime,
     Won,
     Started,
     Restarted,
@@ -404,8 +516,10 @@ fn is_santa_dead(
     mut game_ew: EventWriter<GameEvent>,
 ) {
     for (_, mut health, mut transform) in santa_query.iter_mut() {
-        if health.health <= 0 {
+        // Running out of Christmas spirit is just as bad as getting shot down
+        if health.health <= 0 || health.christmas_spirit <= 0 {
             health.health = 100;
+            health.christmas_spirit = 100;
             transform.translation = Vec3::new(0.0, 0.0, 0.0);
             game_ew.send(GameEvent { event_type: GameEventTypes::Lost });
         }
@@ -0,0 +1,472 @@
+use bevy::audio::{AudioSink, AudioSinkPlayback, GlobalVolume, PlaybackSettings, Volume};
+use bevy::pbr::{CascadeShadowConfig, DirectionalLight};
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use crate::constants::SETTINGS_SAVE_DELAY;
+use crate::gift_cam::GiftCam;
+use crate::lights::{shadow_cascades, LightBudget};
+use crate::snow::SnowSettings;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Settings::load())
+            .add_systems(Update, (
+                apply_settings,
+                apply_master_volume.run_if(resource_changed::<Settings>()),
+                save_settings,
+            ))
+        ;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+}
+
+impl QualityPreset {
+    pub fn next(&self) -> Self {
+        match self {
+            QualityPreset::Low => QualityPreset::Medium,
+            QualityPreset::Medium => QualityPreset::High,
+            QualityPreset::High => QualityPreset::Low,
+        }
+    }
+}
+
+/// Which key does what. Photo mode flies with the same forward, backward and turn
+/// keys as Santa, the turn keys move it sideways. Keys left out of the file keep their default.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub forward: KeyCode,
+    pub backward: KeyCode,
+    pub turn_left: KeyCode,
+    pub turn_right: KeyCode,
+    pub build: KeyCode,
+    pub switch_payload: KeyCode,
+    pub switch_camera: KeyCode,
+    pub skip_gift_cam: KeyCode,
+    pub radar_range: KeyCode,
+    pub map: KeyCode,
+    pub restart: KeyCode,
+    pub photo_mode: KeyCode,
+    pub photo_up: KeyCode,
+    pub photo_down: KeyCode,
+    pub photo_fast: KeyCode,
+    pub photo_look_left: KeyCode,
+    pub photo_look_right: KeyCode,
+    pub photo_look_up: KeyCode,
+    pub photo_look_down: KeyCode,
+    pub photo_zoom_in: KeyCode,
+    pub photo_zoom_out: KeyCode,
+    pub photo_fog_thicker: KeyCode,
+    pub photo_fog_thinner: KeyCode,
+    pub photo_darker: KeyCode,
+    pub photo_brighter: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: KeyCode::W,
+            backward: KeyCode::S,
+            turn_left: KeyCode::A,
+            turn_right: KeyCode::D,
+            build: KeyCode::B,
+            switch_payload: KeyCode::C,
+            switch_camera: KeyCode::V,
+            skip_gift_cam: KeyCode::X,
+            radar_range: KeyCode::R,
+            map: KeyCode::M,
+            restart: KeyCode::Space,
+            photo_mode: KeyCode::P,
+            photo_up: KeyCode::E,
+            photo_down: KeyCode::Q,
+            photo_fast: KeyCode::ShiftLeft,
+            photo_look_left: KeyCode::Left,
+            photo_look_right: KeyCode::Right,
+            photo_look_up: KeyCode::Up,
+            photo_look_down: KeyCode::Down,
+            photo_zoom_in: KeyCode::BracketLeft,
+            photo_zoom_out: KeyCode::BracketRight,
+            photo_fog_thicker: KeyCode::Minus,
+            photo_fog_thinner: KeyCode::Equals,
+            photo_darker: KeyCode::Comma,
+            photo_brighter: KeyCode::Period,
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Every binding with its name
+    pub fn named(&self) -> [(&'static str, KeyCode); 25] {
+        [
+            ("forward", self.forward),
+            ("backward", self.backward),
+            ("turn_left", self.turn_left),
+            ("turn_right", self.turn_right),
+            ("build", self.build),
+            ("switch_payload", self.switch_payload),
+            ("switch_camera", self.switch_camera),
+            ("skip_gift_cam", self.skip_gift_cam),
+            ("radar_range", self.radar_range),
+            ("map", self.map),
+            ("restart", self.restart),
+            ("photo_mode", self.photo_mode),
+            ("photo_up", self.photo_up),
+            ("photo_down", self.photo_down),
+            ("photo_fast", self.photo_fast),
+            ("photo_look_left", self.photo_look_left),
+            ("photo_look_right", self.photo_look_right),
+            ("photo_look_up", self.photo_look_up),
+            ("photo_look_down", self.photo_look_down),
+            ("photo_zoom_in", self.photo_zoom_in),
+            ("photo_zoom_out", self.photo_zoom_out),
+            ("photo_fog_thicker", self.photo_fog_thicker),
+            ("photo_fog_thinner", self.photo_fog_thinner),
+            ("photo_darker", self.photo_darker),
+            ("photo_brighter", self.photo_brighter),
+        ]
+    }
+
+    /// The first key that does more than one thing, if there is one
+    pub fn duplicate(&self) -> Option<KeyCode> {
+        let named = self.named();
+        named
+            .iter()
+            .enumerate()
+            .find(|(i, (_, key))| named[i + 1..].iter().any(|(_, other)| other == key))
+            .map(|(_, (_, key))| *key)
+    }
+}
+
+/// Everything the player can configure, saved as RON a moment after it stops changing.
+/// Anything left out of the file is the default.
+#[derive(Resource, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub quality: QualityPreset,
+    pub shadow_cascades: usize,
+    pub snow_density: f32,
+    pub light_budget: usize,
+    pub shadowed_lights: usize,
+    /// The fog never lets you see further than this, whatever the weather
+    pub fog_distance: f32,
+    pub master_volume: f32,
+    pub effects_volume: f32,
+    pub bindings: KeyBindings,
+    pub camera_sensitivity: f32,
+    pub ui_scale: f32,
+    /// 0.0 turns the camera shake off
+    pub screen_shake: f32,
+    /// The red damage flash
+    pub flashing: bool,
+    /// Slow motion on the gift cam
+    pub slow_motion: bool,
+    pub gift_cam: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let mut settings = Self {
+            quality: QualityPreset::High,
+            shadow_cascades: 0,
+            snow_density: 0.0,
+            light_budget: 0,
+            shadowed_lights: 0,
+            fog_distance: 0.0,
+            master_volume: 1.0,
+            effects_volume: 0.3,
+            bindings: KeyBindings::default(),
+            camera_sensitivity: 1.0,
+            ui_scale: 1.0,
+            screen_shake: 1.0,
+            flashing: true,
+            slow_motion: true,
+            gift_cam: true,
+        };
+        settings.set_quality(QualityPreset::High);
+        settings
+    }
+}
+
+impl Settings {
+    /// Picks a preset and everything that comes with it
+    pub fn set_quality(&mut self, quality: QualityPreset) {
+        let (shadow_cascades, snow_density, light_budget, shadowed_lights, fog_distance) = match quality {
+            QualityPreset::Low => (1, 0.25, 4, 0, 600.0),
+            QualityPreset::Medium => (2, 0.5, 8, 1, 1000.0),
+            QualityPreset::High => (4, 1.0, 16, 4, 1500.0),
+        };
+        self.quality = quality;
+        self.shadow_cascades = shadow_cascades;
+        self.snow_density = snow_density;
+        self.light_budget = light_budget;
+        self.shadowed_lights = shadowed_lights;
+        self.fog_distance = fog_distance;
+    }
+
+    pub fn to_text(&self) -> String {
+        ron::ser::to_string_pretty(self, PrettyConfig::default()).expect("settings always turn into RON")
+    }
+
+    /// A file that doesn't parse is skipped with a warning, a broken file never stops the game
+    pub fn from_text(text: &str) -> Self {
+        match ron::from_str::<Settings>(text) {
+            Ok(settings) => settings.validated(),
+            Err(error) => {
+                warn!("Ignoring the settings file, using the defaults: {}", error);
+                Settings::default()
+            }
+        }
+    }
+
+    /// Pulls everything back into range, whatever was typed into the file
+    fn validated(mut self) -> Self {
+        fn number(value: f32, default: f32, min: f32, max: f32) -> f32 {
+            if value.is_finite() { value.clamp(min, max) } else { default }
+        }
+        let default = Settings::default();
+        self.shadow_cascades = self.shadow_cascades.clamp(1, 4);
+        self.snow_density = number(self.snow_density, default.snow_density, 0.0, 1.0);
+        self.light_budget = self.light_budget.min(64);
+        self.shadowed_lights = self.shadowed_lights.min(16);
+        self.fog_distance = number(self.fog_distance, default.fog_distance, 100.0, 10000.0);
+        self.master_volume = number(self.master_volume, default.master_volume, 0.0, 1.0);
+        self.effects_volume = number(self.effects_volume, default.effects_volume, 0.0, 1.0);
+        self.camera_sensitivity = number(self.camera_sensitivity, default.camera_sensitivity, 0.1, 5.0);
+        self.ui_scale = number(self.ui_scale, default.ui_scale, 0.5, 3.0);
+        self.screen_shake = number(self.screen_shake, default.screen_shake, 0.0, 1.0);
+        // Half the game would be out of reach, better to start over with keys that work
+        if let Some(key) = self.bindings.duplicate() {
+            warn!("{:?} is bound to more than one thing, using the default keys", key);
+            self.bindings = KeyBindings::default();
+        }
+        self
+    }
+
+    pub fn load() -> Self {
+        match storage::read(SETTINGS_FILE) {
+            Some(text) => Settings::from_text(&text),
+            None => Settings::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        storage::write(SETTINGS_FILE, &self.to_text());
+    }
+}
+
+const SETTINGS_FILE: &str = "settings";
+
+/// Small text files that outlive the game, on disk or in the browser's localStorage
+#[cfg(not(target_arch = "wasm32"))]
+pub mod storage {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+    use bevy::log::warn;
+
+    fn config_dir() -> Option<PathBuf> {
+        #[cfg(target_os = "windows")]
+        let base = std::env::var_os("APPDATA").map(PathBuf::from);
+        #[cfg(target_os = "macos")]
+        let base = std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"));
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+        base.map(|base| base.join(env!("CARGO_PKG_NAME")))
+    }
+
+    fn path(name: &str) -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(format!("{}.cfg", name)))
+    }
+
+    pub fn read(name: &str) -> Option<String> {
+        let path = path(name)?;
+        match fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => {
+                warn!("Could not read {}, starting from scratch: {}", path.display(), error);
+                None
+            }
+        }
+    }
+
+    pub fn write(name: &str, text: &str) {
+        let Some(path) = path(name) else {
+            warn!("Nowhere to save the {}", name);
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(error) = fs::create_dir_all(dir) {
+                warn!("Could not create {}: {}", dir.display(), error);
+                return;
+            }
+        }
+        if let Err(error) = fs::write(&path, text) {
+            warn!("Could not save {}: {}", path.display(), error);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub mod storage {
+    use bevy::log::warn;
+
+    fn key(name: &str) -> String {
+        format!("{}.{}", env!("CARGO_PKG_NAME"), name)
+    }
+
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok().flatten()
+    }
+
+    pub fn read(name: &str) -> Option<String> {
+        local_storage()?.get_item(&key(name)).ok().flatten()
+    }
+
+    pub fn write(name: &str, text: &str) {
+        let Some(storage) = local_storage() else {
+            warn!("No localStorage, the {} won't be saved", name);
+            return;
+        };
+        if storage.set_item(&key(name), text).is_err() {
+            warn!("Could not save the {} to localStorage", name);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn apply_settings(
+    settings: Res<Settings>,
+    mut snow_settings: ResMut<SnowSettings>,
+    mut light_budget: ResMut<LightBudget>,
+    mut gift_cam: ResMut<GiftCam>,
+    mut ui_scale: ResMut<UiScale>,
+    mut global_volume: ResMut<GlobalVolume>,
+    mut cascades: Query<&mut CascadeShadowConfig, With<DirectionalLight>>,
+    mut applied_cascades: Local<usize>,
+) {
+    if !settings.is_changed() && *applied_cascades == settings.shadow_cascades {
+        return;
+    }
+    snow_settings.density = settings.snow_density;
+    light_budget.max_active = settings.light_budget;
+    light_budget.max_shadowed = settings.shadowed_lights;
+    gift_cam.enabled = settings.gift_cam;
+    ui_scale.0 = settings.ui_scale as f64;
+    *global_volume = GlobalVolume::new(settings.master_volume);
+    // The lights are spawned a frame in, keep at it until they are there
+    for mut cascade_shadow_config in cascades.iter_mut() {
+        *cascade_shadow_config = shadow_cascades(settings.shadow_cascades);
+        *applied_cascades = settings.shadow_cascades;
+    }
+}
+
+/// `GlobalVolume` only reaches sounds that start after it changes,
+/// the ones already playing are turned up or down here
+fn apply_master_volume(
+    settings: Res<Settings>,
+    sinks: Query<(&AudioSink, &PlaybackSettings)>,
+) {
+    for (sink, playback_settings) in sinks.iter() {
+        match playback_settings.volume {
+            Volume::Relative(volume) => sink.set_volume(volume.get() * settings.master_volume),
+            Volume::Absolute(volume) => sink.set_volume(volume.get()),
+        }
+    }
+}
+
+/// Waits for the settings to stop changing before writing them, dragging a slider
+/// changes them every frame. Whatever is still waiting gets saved on the way out.
+fn save_settings(
+    settings: Res<Settings>,
+    mut save_at: Local<Option<f32>>,
+    mut app_exit_er: EventReader<AppExit>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed_seconds();
+    if settings.is_changed() && !settings.is_added() {
+        *save_at = Some(now + SETTINGS_SAVE_DELAY);
+    }
+    let exiting = app_exit_er.read().count() > 0;
+    if save_at.map_or(false, |save_at| exiting || now >= save_at) {
+        settings.save();
+        *save_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tweaked() -> Settings {
+        let mut settings = Settings::default();
+        settings.set_quality(QualityPreset::Medium);
+        settings.light_budget = 12;
+        settings.fog_distance = 1234.5;
+        settings.master_volume = 0.35;
+        settings.camera_sensitivity = 2.25;
+        settings.flashing = false;
+        settings.gift_cam = false;
+        settings.bindings.forward = KeyCode::I;
+        settings.bindings.restart = KeyCode::Return;
+        settings
+    }
+
+    #[test]
+    fn round_trips_through_ron() {
+        for settings in [Settings::default(), tweaked()] {
+            assert_eq!(Settings::from_text(&settings.to_text()), settings);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_defaults_on_garbage() {
+        for text in ["", "this is not a setting", "(master_volume: loud)", "(gift_cam: false", "\u{1F385}"] {
+            assert_eq!(Settings::from_text(text), Settings::default(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn clamps_out_of_range_numbers() {
+        let text = "(master_volume: 7.0, snow_density: -3.0, fog_distance: 1e30, light_budget: 9000, shadow_cascades: 0, ui_scale: NaN, screen_shake: inf)";
+        let settings = Settings::from_text(text);
+        assert_eq!(settings.master_volume, 1.0);
+        assert_eq!(settings.snow_density, 0.0);
+        assert_eq!(settings.fog_distance, 10000.0);
+        assert_eq!(settings.light_budget, 64);
+        assert_eq!(settings.shadow_cascades, 1);
+        assert_eq!(settings.ui_scale, Settings::default().ui_scale);
+        assert_eq!(settings.screen_shake, Settings::default().screen_shake);
+    }
+
+    #[test]
+    fn missing_and_unknown_fields_are_fine() {
+        let text = "(volume: 0.5, gift_cam: false, bindings: (jump: J, backward: Z))";
+        let settings = Settings::from_text(text);
+        assert!(!settings.gift_cam);
+        assert_eq!(settings.bindings.backward, KeyCode::Z);
+        assert_eq!(settings.bindings.forward, KeyBindings::default().forward);
+        assert_eq!(settings.master_volume, Settings::default().master_volume);
+    }
+
+    #[test]
+    fn falls_back_to_the_default_keys_on_duplicates() {
+        assert_eq!(KeyBindings::default().duplicate(), None);
+        let settings = Settings::from_text("(master_volume: 0.5, bindings: (build: W))");
+        assert_eq!(settings.bindings, KeyBindings::default());
+        assert_eq!(settings.master_volume, 0.5);
+    }
+}
@@ -1,103 +1,302 @@
-use bevy::app::{App, FixedUpdate, Plugin, Update};
+use bevy::app::{App, Plugin, Update};
+use bevy::asset::{Assets, Handle};
 use bevy::core::Name;
 use bevy::hierarchy::DespawnRecursiveExt;
-use bevy::math::{Vec3};
-use bevy::pbr::{PbrBundle};
-use bevy::prelude::{Commands, Component, Entity, Fixed, Query, Res, ResMut, Time, Transform, With};
+use bevy::math::Vec3;
+use bevy::pbr::{NotShadowCaster, NotShadowReceiver, PbrBundle};
+use bevy::prelude::{Commands, Entity, GlobalTransform, IntoSystemConfigs, Mesh, Query, Res, ResMut, Resource, Time, With};
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::view::NoFrustumCulling;
+use bevy::utils::default;
 use bevy_turborand::{DelegatedRng, GlobalRng};
-use bevy_xpbd_3d::components::{CollisionLayers, Position, RigidBody};
-use bevy_xpbd_3d::prelude::{ExternalForce, LinearVelocity};
 use crate::assets::SantasAssets;
-use crate::constants::{SNOW_CEILING, SNOW_SPAWN_SIZE};
-use crate::input::CoolDown;
-use crate::santa::{CollisionLayer, Santa};
+use crate::camera::GameCamera;
+use crate::constants::{SNOW_BOX_HALF_HEIGHT, SNOW_BOX_HALF_SIZE, SNOW_FLAKE_SIZE, SNOW_POOL_SIZE};
+use crate::weather::Weather;
 
 pub struct SnowPlugin;
 
 impl Plugin for SnowPlugin {
     fn build(&self, app: &mut App) {
         app
-            .insert_resource(Time::<Fixed>::from_seconds(0.05))
+            .init_resource::<SnowSettings>()
+            .init_resource::<SnowPool>()
             .add_systems(
                 Update,
                 (
-                    kill_snow,
-                ),
-            )
-            .add_systems(
-                FixedUpdate, (
-                    spawn_snow,
-                ));
+                    fill_snow_pool,
+                    move_snow,
+                ).chain(),
+            );
     }
 }
 
-#[derive(Component)]
-pub struct Snow {
-    time_to_live: f32
+/// How much of the pool we use, 1.0 is all `SNOW_POOL_SIZE` flakes.
+/// Lower it on slow machines.
+#[derive(Resource)]
+pub struct SnowSettings {
+    pub density: f32,
 }
 
-impl Snow {
-    pub fn new(time_to_live: f32) -> Self {
-        Self {
-            time_to_live,
-        }
+impl Default for SnowSettings {
+    fn default() -> Self {
+        Self { density: 1.0 }
     }
 }
 
-impl CoolDown for Snow {
-    fn cool_down(&mut self, delta: f32) -> bool {
-        self.time_to_live -= delta;
-        self.time_to_live <= 0.0
+impl SnowSettings {
+    pub fn pool_size(&self) -> usize {
+        (SNOW_POOL_SIZE as f32 * self.density.clamp(0.0, 1.0)) as usize
     }
 }
 
-fn kill_snow(
+/// Every snowflake there is. They are simulated here on the CPU, no physics involved,
+/// and recycled around the camera forever. All of them are drawn as a single mesh that
+/// is rewritten every frame, so the whole snowfall is one entity.
+#[derive(Resource, Default)]
+pub struct SnowPool {
+    pub entity: Option<Entity>,
+    pub mesh: Handle<Mesh>,
+    pub positions: Vec<Vec3>,
+    /// Each flake's own tumbling, the wind is added on top
+    pub drifts: Vec<Vec3>,
+}
+
+/// Rebuilds the pool when the density setting changes
+fn fill_snow_pool(
     mut commands: Commands,
-    mut snow_query: Query<(Entity, &mut Snow)>,
-    time: Res<Time>,
+    mut snow_pool: ResMut<SnowPool>,
+    snow_settings: Res<SnowSettings>,
+    santas_assets: Res<SantasAssets>,
+    camera_query: Query<&GlobalTransform, With<GameCamera>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut global_rng: ResMut<GlobalRng>,
 ) {
-    for (entity, mut snow) in snow_query.iter_mut() {
-        if snow.cool_down(time.delta_seconds()) {
-            commands.entity(entity).despawn_recursive();
+    let pool_size = snow_settings.pool_size();
+    if snow_pool.entity.is_some() && snow_pool.positions.len() == pool_size {
+        return;
+    }
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    if let Some(entity) = snow_pool.entity.take() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let center = camera_transform.translation();
+    let positions = (0..pool_size)
+        .map(|_| center + Vec3::new(
+            global_rng.f32_normalized() * SNOW_BOX_HALF_SIZE,
+            global_rng.f32_normalized() * SNOW_BOX_HALF_HEIGHT,
+            global_rng.f32_normalized() * SNOW_BOX_HALF_SIZE,
+        ))
+        .collect();
+    let drifts = (0..pool_size)
+        .map(|_| Vec3::new(global_rng.f32_normalized() * 1.5, -1.0 - global_rng.f32() * 3.0, global_rng.f32_normalized() * 1.5))
+        .collect();
+    let mesh = meshes.add(build_snow_mesh(pool_size));
+    let entity = commands.spawn((
+        Name::from("Snow"),
+        PbrBundle {
+            mesh: mesh.clone(),
+            material: santas_assets.snowball_material.clone(),
+            ..default()
+        },
+        // The flakes move every frame, the bounds the mesh was spawned with mean nothing
+        NoFrustumCulling,
+        NotShadowCaster,
+        NotShadowReceiver,
+    )).id();
+    *snow_pool = SnowPool {
+        entity: Some(entity),
+        mesh,
+        positions,
+        drifts,
+    };
+}
+
+/// One quad per flake, the corners are filled in by `move_snow`
+fn build_snow_mesh(pool_size: usize) -> Mesh {
+    let triangles: Vec<u32> = (0..pool_size as u32)
+        .flat_map(|index| {
+            let first = index * 4;
+            [first, first + 1, first + 2, first, first + 2, first + 3]
+        })
+        .collect();
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.0f32; 3]; pool_size * 4]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0f32, 1.0, 0.0]; pool_size * 4]);
+    mesh.set_indices(Some(Indices::U32(triangles)));
+    mesh
+}
+
+/// Moves a flake with its drift and the wind, then wraps it back into the box
+/// around the camera so the pool never runs out.
+pub fn simulate_flake(position: Vec3, velocity: Vec3, center: Vec3, delta: f32) -> Vec3 {
+    let mut position = position + velocity * delta;
+    let half_extents = Vec3::new(SNOW_BOX_HALF_SIZE, SNOW_BOX_HALF_HEIGHT, SNOW_BOX_HALF_SIZE);
+    let local = position - center;
+    for axis in 0..3 {
+        if local[axis] > half_extents[axis] {
+            position[axis] -= 2.0 * half_extents[axis];
+        } else if local[axis] < -half_extents[axis] {
+            position[axis] += 2.0 * half_extents[axis];
         }
     }
+    position
 }
 
-fn spawn_snow(
-    mut commands: Commands,
-    where_is_santa: Query<&Transform, With<Santa>>,
-    santas_assets: Res<SantasAssets>,
-    mut global_rng: ResMut<GlobalRng>,
+/// Moves every flake and turns its quad towards the camera. The weather says how
+/// hard it snows, the flakes beyond that are squashed down to nothing.
+fn move_snow(
+    mut snow_pool: ResMut<SnowPool>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    camera_query: Query<&GlobalTransform, With<GameCamera>>,
+    weather: Res<Weather>,
+    time: Res<Time>,
 ) {
-    if let Ok(santa_position) = where_is_santa.get_single() {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let center = camera_transform.translation();
+    let delta = time.delta_seconds();
+    let pool = &mut *snow_pool;
+    for (position, drift) in pool.positions.iter_mut().zip(pool.drifts.iter()) {
+        *position = simulate_flake(*position, *drift + weather.wind, center, delta);
+    }
+
+    let Some(mesh) = meshes.get_mut(&pool.mesh) else {
+        return;
+    };
+    let showing = (pool.positions.len() as f32 * weather.snowfall.clamp(0.0, 1.0)) as usize;
+    let right = camera_transform.right() * SNOW_FLAKE_SIZE;
+    let up = camera_transform.up() * SNOW_FLAKE_SIZE;
+    let corners = [-right - up, right - up, right + up, up - right];
+    let vertices: Vec<[f32; 3]> = pool.positions
+        .iter()
+        .enumerate()
+        .flat_map(|(index, position)| {
+            let size = if index < showing { 1.0 } else { 0.0 };
+            corners.map(|corner| (*position + corner * size).to_array())
+        })
+        .collect();
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+}
+
+/// Headless comparison between the old physics snow and the pool. Run with
+/// `cargo test --release snow_benchmark -- --ignored --nocapture`
+#[cfg(test)]
+mod benchmark {
+    use std::time::{Duration, Instant};
+    use bevy::app::{App, FixedUpdate, Update};
+    use bevy::asset::{AssetApp, AssetPlugin};
+    use bevy::core::Name;
+    use bevy::math::Vec3;
+    use bevy::MinimalPlugins;
+    use bevy::pbr::{PbrBundle, StandardMaterial};
+    use bevy::prelude::{Commands, Component, Entity, Fixed, Mesh, Query, Res, ResMut, Time, TransformBundle, TransformPlugin};
+    use bevy::hierarchy::{DespawnRecursiveExt, HierarchyPlugin};
+    use bevy::time::TimeUpdateStrategy;
+    use bevy_turborand::{DelegatedRng, GlobalRng};
+    use bevy_turborand::prelude::RngPlugin;
+    use bevy_xpbd_3d::components::{CollisionLayers, Position, RigidBody};
+    use bevy_xpbd_3d::plugins::PhysicsPlugins;
+    use bevy_xpbd_3d::prelude::{ExternalForce, LinearVelocity};
+    use crate::assets::SantasAssets;
+    use crate::camera::GameCamera;
+    use crate::santa::CollisionLayer;
+    use crate::weather::{Weather, WeatherProfile};
+    use super::*;
+
+    const FRAMES: u32 = 300;
+    const FRAME_TIME: f32 = 1.0 / 60.0;
+
+    /// The old snow, spawned 50 at a time every 50 ms and despawned after 10 s
+    #[derive(Component)]
+    struct LegacySnow(f32);
+
+    fn legacy_spawn_snow(
+        mut commands: Commands,
+        santas_assets: Res<SantasAssets>,
+        mut global_rng: ResMut<GlobalRng>,
+    ) {
         for _n in 0..50 {
-            let x = global_rng.f32_normalized() * SNOW_SPAWN_SIZE;
-            let z = global_rng.f32() * SNOW_SPAWN_SIZE;
-            let y = global_rng.f32() * SNOW_CEILING;
+            commands.spawn((
+                Name::from("SnowFlake"),
+                LegacySnow(10.0),
+                PbrBundle {
+                    mesh: santas_assets.snowball_mesh.clone(),
+                    material: santas_assets.snowball_material.clone(),
+                    ..Default::default()
+                },
+                ExternalForce::new(Vec3::ZERO),
+                Position::new(Vec3::new(global_rng.f32_normalized() * 50.0, global_rng.f32() * 20.0, global_rng.f32() * 50.0)),
+                RigidBody::Kinematic,
+                LinearVelocity::from(Vec3::new(global_rng.f32() * 5.0, -global_rng.f32() * 3.0, global_rng.f32_normalized() * 2.0)),
+                CollisionLayers::new([CollisionLayer::Snow], [CollisionLayer::Nothing]),
+            ));
+        }
+    }
 
-            let snow_direction = Vec3::new(x, y, z);
+    fn legacy_kill_snow(
+        mut commands: Commands,
+        mut snow_query: Query<(Entity, &mut LegacySnow)>,
+        time: Res<Time>,
+    ) {
+        for (entity, mut snow) in snow_query.iter_mut() {
+            snow.0 -= time.delta_seconds();
+            if snow.0 <= 0.0 {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
 
-            let snow_position = santa_position.translation + santa_position.rotation.mul_vec3(snow_direction);
+    fn headless_app() -> App {
+        let mut app = App::new();
+        app
+            .add_plugins((MinimalPlugins, TransformPlugin, HierarchyPlugin, AssetPlugin::default()))
+            .init_asset::<Mesh>()
+            .init_asset::<StandardMaterial>()
+            .add_plugins(RngPlugin::new().with_rng_seed(2412))
+            .init_resource::<SantasAssets>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(FRAME_TIME)))
+            .insert_resource(Weather::new(WeatherProfile::Blizzard));
+        app.world.spawn((TransformBundle::default(), GameCamera {}));
+        app
+    }
 
-            commands.spawn(
-                (
-                    Name::from("SnowFlake"),
-                    Snow::new(10.0),
-                    PbrBundle {
-                        mesh: santas_assets.snowball_mesh.clone(),
-                        material: santas_assets.snowball_material.clone(),
-                        ..Default::default()
-                    },
-                    ExternalForce::new(Vec3::ZERO),
-                    Position::new(snow_position),
-                    RigidBody::Kinematic,
-                    LinearVelocity::from(Vec3::new(global_rng.f32() * 5.0, -global_rng.f32() * 3.0, global_rng.f32_normalized() * 2.0)),
-                    CollisionLayers::new(
-                        [CollisionLayer::Snow],
-                        [
-                            CollisionLayer::Nothing,
-                        ]),
-                ));
+    /// Steps the app with a fixed frame time so both approaches simulate the same
+    /// amount of snow time, returns the time spent updating and the final entity count
+    fn run(app: &mut App) -> (Duration, usize) {
+        let started = Instant::now();
+        for _ in 0..FRAMES {
+            app.update();
         }
+        let elapsed = started.elapsed();
+        let count = app.world.query::<Entity>().iter(&app.world).count();
+        (elapsed, count)
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[ignore]
+    fn snow_benchmark() {
+        let mut legacy = headless_app();
+        legacy
+            .add_plugins(PhysicsPlugins::default())
+            .insert_resource(Time::<Fixed>::from_seconds(0.05))
+            .add_systems(FixedUpdate, legacy_spawn_snow)
+            .add_systems(Update, legacy_kill_snow);
+        let (legacy_time, legacy_entities) = run(&mut legacy);
+
+        let mut pooled = headless_app();
+        pooled.add_plugins(SnowPlugin);
+        let (pooled_time, pooled_entities) = run(&mut pooled);
+
+        println!("legacy snow: {} entities, {:?} for {} frames", legacy_entities, legacy_time, FRAMES);
+        println!("pooled snow: {} entities, {:?} for {} frames", pooled_entities, pooled_time, FRAMES);
+        let snow_pool = pooled.world.resource::<SnowPool>();
+        assert_eq!(snow_pool.positions.len(), SnowSettings::default().pool_size());
+        assert!(pooled_entities < legacy_entities, "the pool should need fewer entities");
+        assert!(pooled_time < legacy_time, "the pool should update faster");
+    }
+}
@@ -0,0 +1,276 @@
+use bevy::app::{App, Plugin, Startup};
+use bevy::asset::{Assets, Handle};
+use bevy::core::Name;
+use bevy::math::{IVec2, Vec2, Vec3};
+use bevy::pbr::{PbrBundle, StandardMaterial};
+use bevy::prelude::{Color, Commands, Component, Entity, Mesh, ResMut, Resource, Transform};
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::utils::default;
+use bevy_xpbd_3d::components::{Collider, CollisionLayers, RigidBody};
+use crate::constants::{GROUND_PLANE, LAKE_LEVEL, TERRAIN_AMPLITUDE, TERRAIN_CHUNK_RESOLUTION, TERRAIN_CHUNK_SIZE, TERRAIN_FLAT_RADIUS, TERRAIN_FLATTEN_FALLOFF, TERRAIN_MAX_HEIGHT, TERRAIN_NOISE_SCALE, TERRAIN_SEED};
+use crate::santa::CollisionLayer;
+
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Terrain::new(TERRAIN_SEED))
+            .init_resource::<TerrainAssets>()
+            .add_systems(Startup, load_terrain_assets)
+        ;
+    }
+}
+
+/// The heightmap the whole world is built on. Everything that needs to stand on
+/// the ground asks `height_at` instead of using `GROUND_PLANE` directly.
+#[derive(Resource)]
+pub struct Terrain {
+    pub seed: u32,
+    /// Villages level the ground they are built on
+    pub flat_sites: Vec<FlatSite>,
+}
+
+/// A circle of ground that is flattened down to `GROUND_PLANE`, fading back
+/// into the hills over `TERRAIN_FLATTEN_FALLOFF`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FlatSite {
+    pub center: Vec2,
+    pub radius: f32,
+}
+
+impl FlatSite {
+    /// Whether any of the ground this site touches is inside the chunk
+    pub fn overlaps_chunk(&self, chunk: IVec2) -> bool {
+        let origin = Terrain::chunk_origin(chunk);
+        let min = Vec2::new(origin.x, origin.z);
+        let closest = self.center.clamp(min, min + Vec2::splat(TERRAIN_CHUNK_SIZE));
+        closest.distance(self.center) < self.radius + TERRAIN_FLATTEN_FALLOFF
+    }
+}
+
+impl Terrain {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            flat_sites: Vec::new(),
+        }
+    }
+
+    /// Height of the terrain surface, lakes are frozen flat at `LAKE_LEVEL`
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        GROUND_PLANE + self.raw_height_at(x, z).max(LAKE_LEVEL)
+    }
+
+    pub fn is_lake(&self, x: f32, z: f32) -> bool {
+        self.raw_height_at(x, z) < LAKE_LEVEL
+    }
+
+    /// Inside the ground a village has claimed
+    pub fn is_flat_site(&self, x: f32, z: f32) -> bool {
+        self.flat_sites.iter().any(|site| site.center.distance(Vec2::new(x, z)) < site.radius)
+    }
+
+    /// Direction along the ground that goes downhill the fastest
+    pub fn downhill_at(&self, x: f32, z: f32) -> Vec3 {
+        let step = 1.0;
+        let dx = self.height_at(x + step, z) - self.height_at(x - step, z);
+        let dz = self.height_at(x, z + step) - self.height_at(x, z - step);
+        Vec3::new(-dx, 0.0, -dz).normalize_or_zero()
+    }
+
+    pub fn normal_at(&self, x: f32, z: f32) -> Vec3 {
+        let step = 1.0;
+        let dx = self.height_at(x + step, z) - self.height_at(x - step, z);
+        let dz = self.height_at(x, z + step) - self.height_at(x, z - step);
+        Vec3::new(-dx, 2.0 * step, -dz).normalize()
+    }
+
+    /// Height relative to `GROUND_PLANE` before the lakes are filled in. The hills
+    /// fade in away from the origin so Santa always starts in open country, and the
+    /// tops are rounded off below `TERRAIN_MAX_HEIGHT`. The highest of them still reach
+    /// above Santa, those he has to fly around or crash into.
+    fn raw_height_at(&self, x: f32, z: f32) -> f32 {
+        let point = Vec2::new(x, z);
+        let noise = self.fbm(point / TERRAIN_NOISE_SCALE);
+        let hill_factor = (point.length() / TERRAIN_FLAT_RADIUS).clamp(0.0, 1.0);
+        let height = (noise - 0.4) * TERRAIN_AMPLITUDE * hill_factor * hill_factor;
+        let height = if height > 0.0 {
+            TERRAIN_MAX_HEIGHT * (height / TERRAIN_MAX_HEIGHT).tanh()
+        } else {
+            height
+        };
+        height * self.flatten_factor(point)
+    }
+
+    /// 0.0 inside a flat site, 1.0 well away from all of them
+    fn flatten_factor(&self, point: Vec2) -> f32 {
+        self.flat_sites
+            .iter()
+            .map(|site| {
+                let t = ((point.distance(site.center) - site.radius) / TERRAIN_FLATTEN_FALLOFF).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            })
+            .fold(1.0, f32::min)
+    }
+
+    fn fbm(&self, point: Vec2) -> f32 {
+        let mut amplitude = 0.5;
+        let mut frequency = 1.0;
+        let mut total = 0.0;
+        let mut normalization = 0.0;
+        for octave in 0..4 {
+            total += amplitude * self.value_noise(point * frequency, octave);
+            normalization += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        total / normalization
+    }
+
+    fn value_noise(&self, point: Vec2, octave: u32) -> f32 {
+        let cell = point.floor();
+        let local = point - cell;
+        // Smoothstep so the slopes line up between cells
+        let t = local * local * (Vec2::splat(3.0) - 2.0 * local);
+        let (ix, iz) = (cell.x as i32, cell.y as i32);
+
+        let a = self.hash(ix, iz, octave);
+        let b = self.hash(ix + 1, iz, octave);
+        let c = self.hash(ix, iz + 1, octave);
+        let d = self.hash(ix + 1, iz + 1, octave);
+        let top = a + (b - a) * t.x;
+        let bottom = c + (d - c) * t.x;
+        top + (bottom - top) * t.y
+    }
+
+    /// Integer hash mapped to 0..1, the same input always gives the same value
+    fn hash(&self, x: i32, z: i32, octave: u32) -> f32 {
+        let mut h = (x as u32).wrapping_mul(0x27d4_eb2d)
+            ^ (z as u32).wrapping_mul(0x1656_67b1)
+            ^ self.seed.wrapping_add(octave).wrapping_mul(0x9e37_79b9);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 13;
+        h = h.wrapping_mul(0xc2b2_ae35);
+        h ^= h >> 16;
+        h as f32 / u32::MAX as f32
+    }
+
+    pub fn chunk_origin(chunk: IVec2) -> Vec3 {
+        Vec3::new(chunk.x as f32 * TERRAIN_CHUNK_SIZE, 0.0, chunk.y as f32 * TERRAIN_CHUNK_SIZE)
+    }
+
+    /// Builds the render mesh and the trimesh collider data for one chunk.
+    /// Vertices are local to the chunk origin.
+    pub fn build_chunk(&self, chunk: IVec2) -> (Mesh, Vec<Vec3>, Vec<[u32; 3]>) {
+        let origin = Self::chunk_origin(chunk);
+        let vertices_per_side = TERRAIN_CHUNK_RESOLUTION + 1;
+        let step = TERRAIN_CHUNK_SIZE / TERRAIN_CHUNK_RESOLUTION as f32;
+
+        let mut positions: Vec<Vec3> = Vec::with_capacity((vertices_per_side * vertices_per_side) as usize);
+        let mut normals: Vec<[f32; 3]> = Vec::with_capacity(positions.capacity());
+        let mut colors: Vec<[f32; 4]> = Vec::with_capacity(positions.capacity());
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(positions.capacity());
+
+        for row in 0..vertices_per_side {
+            for column in 0..vertices_per_side {
+                let local_x = column as f32 * step;
+                let local_z = row as f32 * step;
+                let (world_x, world_z) = (origin.x + local_x, origin.z + local_z);
+                let height = self.height_at(world_x, world_z);
+
+                positions.push(Vec3::new(local_x, height, local_z));
+                normals.push(self.normal_at(world_x, world_z).to_array());
+                colors.push(self.color_at(world_x, world_z).as_rgba_f32());
+                uvs.push([column as f32 / TERRAIN_CHUNK_RESOLUTION as f32, row as f32 / TERRAIN_CHUNK_RESOLUTION as f32]);
+            }
+        }
+
+        let mut triangles: Vec<[u32; 3]> = Vec::with_capacity((TERRAIN_CHUNK_RESOLUTION * TERRAIN_CHUNK_RESOLUTION * 2) as usize);
+        for row in 0..TERRAIN_CHUNK_RESOLUTION {
+            for column in 0..TERRAIN_CHUNK_RESOLUTION {
+                let top_left = row * vertices_per_side + column;
+                let top_right = top_left + 1;
+                let bottom_left = top_left + vertices_per_side;
+                let bottom_right = bottom_left + 1;
+                // Counter-clockwise seen from above
+                triangles.push([top_left, bottom_left, top_right]);
+                triangles.push([top_right, bottom_left, bottom_right]);
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.iter().map(|p| p.to_array()).collect::<Vec<_>>());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        mesh.set_indices(Some(Indices::U32(triangles.iter().flatten().copied().collect())));
+
+        (mesh, positions, triangles)
+    }
+
+    fn color_at(&self, x: f32, z: f32) -> Color {
+        if self.is_lake(x, z) {
+            return Color::rgb(0.55, 0.7, 0.85);
+        }
+        // Dark pine valleys, snowy hill tops
+        let height = ((self.height_at(x, z) - GROUND_PLANE - LAKE_LEVEL) / (TERRAIN_MAX_HEIGHT - LAKE_LEVEL)).clamp(0.0, 1.0);
+        let valley = Vec3::new(0.05, 0.15, 0.08);
+        let snow = Vec3::new(0.9, 0.92, 0.95);
+        let color = valley.lerp(snow, height.sqrt());
+        Color::rgb(color.x, color.y, color.z)
+    }
+}
+
+#[derive(Component)]
+pub struct TerrainChunk {
+    pub coordinates: IVec2,
+}
+
+#[derive(Resource, Default)]
+pub struct TerrainAssets {
+    pub material: Handle<StandardMaterial>,
+}
+
+pub fn load_terrain_assets(
+    mut terrain_assets: ResMut<TerrainAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    *terrain_assets = TerrainAssets {
+        // The colour comes from the vertices
+        material: materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            perceptual_roughness: 0.8,
+            ..default()
+        }),
+    }
+}
+
+pub fn spawn_terrain_chunk(
+    commands: &mut Commands,
+    terrain: &Terrain,
+    terrain_assets: &TerrainAssets,
+    meshes: &mut Assets<Mesh>,
+    chunk: IVec2,
+) -> Entity {
+    let (mesh, vertices, triangles) = terrain.build_chunk(chunk);
+    commands.spawn((
+        Name::from(format!("Terrain {} {}", chunk.x, chunk.y)),
+        TerrainChunk { coordinates: chunk },
+        PbrBundle {
+            mesh: meshes.add(mesh),
+            material: terrain_assets.material.clone(),
+            transform: Transform::from_translation(Terrain::chunk_origin(chunk)),
+            ..default()
+        },
+        RigidBody::Static,
+        Collider::trimesh(vertices, triangles),
+        CollisionLayers::new(
+            [CollisionLayer::Ground],
+            [
+                CollisionLayer::Santa,
+            ]),
+    )).id()
+}
@@ -0,0 +1,324 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+use belly::prelude::*;
+use bevy::audio::{Pitch, PitchBundle, Volume};
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::LinearVelocity;
+use crate::constants::{THREAT_IMPACT_WARNING_TIME, THREAT_LAUNCH_FLASH_TIME, THREAT_LOCK_TIME, THREAT_TRACKING_RANGE};
+use crate::sam_site::{SamSite, SamTarget, SurfaceToAirMissile};
+use crate::santa::Santa;
+use crate::settings::Settings;
+use crate::ui::set_if_changed;
+
+pub struct ThreatPlugin;
+
+impl Plugin for ThreatPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<ThreatEvent>()
+            .init_resource::<Threats>()
+            .init_resource::<ThreatSounds>()
+            .add_systems(Startup, load_threat_sounds)
+            .add_systems(PostStartup, spawn_threat_hud)
+            .add_systems(
+                Update, (
+                    assess_threats,
+                    threat_hud_system,
+                    threat_audio,
+                ).chain(),
+            )
+        ;
+    }
+}
+
+const SECTOR_NAMES: [&str; 8] = ["AHEAD", "FRONT RIGHT", "RIGHT", "BEHIND RIGHT", "BEHIND", "BEHIND LEFT", "LEFT", "FRONT LEFT"];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ThreatLevel {
+    /// Santa is in range and the site is following him
+    Tracking,
+    /// About to fire
+    Locked,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SiteThreat {
+    pub site: Entity,
+    pub level: ThreatLevel,
+    /// Which of the eight sectors around Santa it is in, 0 is straight ahead going clockwise
+    pub sector: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MissileThreat {
+    pub missile: Entity,
+    pub distance: f32,
+    /// Seconds until it reaches Santa at the current closing speed, infinite if it isn't closing in
+    pub time_to_impact: f32,
+    pub sector: usize,
+    /// The imminent impact warning has gone out already
+    pub warned: bool,
+}
+
+/// Everything that is out to get Santa right now
+#[derive(Resource, Default)]
+pub struct Threats {
+    pub sites: Vec<SiteThreat>,
+    pub missiles: Vec<MissileThreat>,
+    /// Counts down after a launch so the warning stays up long enough to be read
+    pub launch_flash: f32,
+}
+
+impl Threats {
+    pub fn is_locked(&self) -> bool {
+        self.sites.iter().any(|site| site.level == ThreatLevel::Locked)
+    }
+
+    pub fn most_urgent_missile(&self) -> Option<&MissileThreat> {
+        self.missiles.iter().min_by(|a, b| a.time_to_impact.total_cmp(&b.time_to_impact))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ThreatEventTypes {
+    Locked(Entity),
+    Launch(Entity),
+    /// A missile is less than `THREAT_IMPACT_WARNING_TIME` away
+    ImpactImminent { missile: Entity, time_to_impact: f32 },
+}
+
+#[derive(Event)]
+pub struct ThreatEvent(pub ThreatEventTypes);
+
+/// The sector around Santa a position is in, 0 is straight ahead and they go round clockwise
+pub fn threat_sector(santa: &Transform, position: Vec3) -> usize {
+    let offset = santa.rotation.inverse().mul_vec3(position - santa.translation);
+    // Santa flies along his local -Z with +X on his right
+    let angle = offset.x.atan2(-offset.z);
+    ((angle / TAU * 8.0).round() as i32).rem_euclid(8) as usize
+}
+
+/// `offset` goes from the missile to Santa, `relative_velocity` is the missile's minus Santa's
+pub fn time_to_impact(offset: Vec3, relative_velocity: Vec3) -> f32 {
+    let closing_speed = relative_velocity.dot(offset.normalize_or_zero());
+    if closing_speed <= 0.0 {
+        return f32::INFINITY;
+    }
+    offset.length() / closing_speed
+}
+
+fn assess_threats(
+    santa_query: Query<(Entity, &Transform, &LinearVelocity), With<Santa>>,
+    sam_site_query: Query<(Entity, &SamSite, &GlobalTransform)>,
+    missile_query: Query<(Entity, &GlobalTransform, &LinearVelocity, &SamTarget), With<SurfaceToAirMissile>>,
+    mut threats: ResMut<Threats>,
+    mut threat_ew: EventWriter<ThreatEvent>,
+    time: Res<Time>,
+) {
+    let Ok((santa_entity, santa_transform, santa_velocity)) = santa_query.get_single() else {
+        return;
+    };
+    threats.launch_flash = (threats.launch_flash - time.delta_seconds()).max(0.0);
+
+    let mut sites = Vec::new();
+    for (site_entity, sam_site, site_transform) in sam_site_query.iter() {
+        let position = site_transform.translation();
+        if position.distance(santa_transform.translation) > THREAT_TRACKING_RANGE {
+            continue;
+        }
+        let level = if sam_site.time_left < THREAT_LOCK_TIME { ThreatLevel::Locked } else { ThreatLevel::Tracking };
+        sites.push(SiteThreat { site: site_entity, level, sector: threat_sector(santa_transform, position) });
+    }
+    for site in sites.iter() {
+        let previous = threats.sites.iter().find(|previous| previous.site == site.site).map(|previous| previous.level);
+        if site.level == ThreatLevel::Locked && previous != Some(ThreatLevel::Locked) {
+            threat_ew.send(ThreatEvent(ThreatEventTypes::Locked(site.site)));
+        }
+    }
+
+    let mut missiles = Vec::new();
+    for (missile_entity, missile_transform, missile_velocity, target) in missile_query.iter() {
+        if target.0 != santa_entity {
+            continue;
+        }
+        let position = missile_transform.translation();
+        let offset = santa_transform.translation - position;
+        let previous = threats.missiles.iter().find(|previous| previous.missile == missile_entity);
+        let mut missile = MissileThreat {
+            missile: missile_entity,
+            distance: offset.length(),
+            time_to_impact: time_to_impact(offset, missile_velocity.0 - santa_velocity.0),
+            sector: threat_sector(santa_transform, position),
+            warned: previous.map_or(false, |previous| previous.warned),
+        };
+        if previous.is_none() {
+            threats.launch_flash = THREAT_LAUNCH_FLASH_TIME;
+            threat_ew.send(ThreatEvent(ThreatEventTypes::Launch(missile_entity)));
+        }
+        if !missile.warned && missile.time_to_impact < THREAT_IMPACT_WARNING_TIME {
+            missile.warned = true;
+            threat_ew.send(ThreatEvent(ThreatEventTypes::ImpactImminent { missile: missile_entity, time_to_impact: missile.time_to_impact }));
+        }
+        missiles.push(missile);
+    }
+
+    threats.sites = sites;
+    threats.missiles = missiles;
+}
+
+/// The text of the warnings, the HUD labels are bound to it
+#[derive(Component, Default)]
+pub struct ThreatHud {
+    pub lock: String,
+    pub launch: String,
+    pub missile: String,
+    pub sectors: String,
+}
+
+fn spawn_threat_hud(mut commands: Commands, mut elements: Elements) {
+    commands.add(ess! {
+        .threat_hud {
+            position-type: absolute;
+            top: 20%;
+            left: 40%;
+            width: 20%;
+            flex-direction: column;
+            align-items: center;
+            font: bold;
+            font-size: 20px;
+            color: #ff2222;
+        }
+        .threat_sectors {
+            font-size: 14px;
+            color: #ffaa00;
+        }
+    });
+    let hud = commands.spawn(ThreatHud::default()).id();
+    elements.select("body").add_child(eml! {
+        <span {hud} c:threat_hud>
+            <label bind:value=from!(hud, ThreatHud:lock)/>
+            <label bind:value=from!(hud, ThreatHud:launch)/>
+            <label bind:value=from!(hud, ThreatHud:missile)/>
+            <label c:threat_sectors bind:value=from!(hud, ThreatHud:sectors)/>
+        </span>
+    });
+}
+
+fn threat_hud_system(
+    threats: Res<Threats>,
+    mut hud_query: Query<&mut ThreatHud>,
+) {
+    if !threats.is_changed() {
+        return;
+    }
+    let Ok(mut hud) = hud_query.get_single_mut() else {
+        return;
+    };
+    let lock = if threats.is_locked() { "LOCK".to_string() } else { String::new() };
+    let launch = if threats.launch_flash > 0.0 { "LAUNCH".to_string() } else { String::new() };
+    let missile = threats
+        .most_urgent_missile()
+        .filter(|missile| missile.time_to_impact.is_finite())
+        .map(|missile| format!("MISSILE {} {:.1}s", SECTOR_NAMES[missile.sector], missile.time_to_impact))
+        .unwrap_or_default();
+    let mut sectors: Vec<usize> = threats.sites.iter().map(|site| site.sector).collect();
+    sectors.sort();
+    sectors.dedup();
+    let sectors = sectors.iter().map(|sector| SECTOR_NAMES[*sector]).collect::<Vec<_>>().join(" ");
+    set_if_changed(&mut hud.lock, lock);
+    set_if_changed(&mut hud.launch, launch);
+    set_if_changed(&mut hud.missile, missile);
+    set_if_changed(&mut hud.sectors, sectors);
+}
+
+/// The synthesised beeps, made once and played over and over
+#[derive(Resource, Default)]
+pub struct ThreatSounds {
+    pub lock: Handle<Pitch>,
+    pub launch: Handle<Pitch>,
+    pub pip: Handle<Pitch>,
+    pub impact: Handle<Pitch>,
+}
+
+fn load_threat_sounds(
+    mut threat_sounds: ResMut<ThreatSounds>,
+    mut pitches: ResMut<Assets<Pitch>>,
+) {
+    *threat_sounds = ThreatSounds {
+        lock: pitches.add(Pitch::new(880.0, Duration::from_secs_f32(0.4))),
+        launch: pitches.add(Pitch::new(1320.0, Duration::from_secs_f32(0.6))),
+        pip: pitches.add(Pitch::new(1760.0, Duration::from_secs_f32(0.05))),
+        impact: pitches.add(Pitch::new(2640.0, Duration::from_secs_f32(0.3))),
+    };
+}
+
+fn beep(commands: &mut Commands, pitch: &Handle<Pitch>, volume: f32) {
+    commands.spawn(PitchBundle {
+        source: pitch.clone(),
+        settings: PlaybackSettings::DESPAWN.with_volume(Volume::new_relative(volume)),
+    });
+}
+
+/// A steady tone on lock, faster and faster pips as a missile closes in and a shriek just before it hits
+fn threat_audio(
+    mut commands: Commands,
+    threat_sounds: Res<ThreatSounds>,
+    mut threat_er: EventReader<ThreatEvent>,
+    threats: Res<Threats>,
+    mut next_pip: Local<f32>,
+    time: Res<Time<Virtual>>,
+    settings: Res<Settings>,
+) {
+    let volume = settings.effects_volume;
+    for threat_event in threat_er.read() {
+        match threat_event.0 {
+            ThreatEventTypes::Locked(_) => beep(&mut commands, &threat_sounds.lock, volume),
+            ThreatEventTypes::Launch(_) => beep(&mut commands, &threat_sounds.launch, volume),
+            ThreatEventTypes::ImpactImminent { .. } => beep(&mut commands, &threat_sounds.impact, volume),
+        }
+    }
+    let Some(missile) = threats.most_urgent_missile().filter(|missile| missile.time_to_impact.is_finite()) else {
+        *next_pip = 0.0;
+        return;
+    };
+    *next_pip -= time.delta_seconds();
+    if *next_pip <= 0.0 {
+        beep(&mut commands, &threat_sounds.pip, volume);
+        *next_pip = (missile.time_to_impact / 5.0).clamp(0.08, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 0.001;
+
+    #[test]
+    fn sectors_go_round_clockwise_from_the_nose() {
+        for turn in [0.0_f32, 90.0, 225.0] {
+            let rotation = Quat::from_rotation_y(turn.to_radians());
+            let santa = Transform::from_xyz(10.0, 5.0, -30.0).with_rotation(rotation);
+            for sector in 0..8 {
+                // Clockwise seen from above is a negative turn around y
+                let local = Quat::from_rotation_y(-(sector as f32) * TAU / 8.0).mul_vec3(Vec3::NEG_Z) * 50.0;
+                let position = santa.translation + rotation.mul_vec3(local);
+                assert_eq!(threat_sector(&santa, position), sector, "Santa turned {}, {}", turn, SECTOR_NAMES[sector]);
+            }
+        }
+        let santa = Transform::IDENTITY;
+        assert_eq!(SECTOR_NAMES[threat_sector(&santa, Vec3::new(0.0, 0.0, -10.0))], "AHEAD");
+        assert_eq!(SECTOR_NAMES[threat_sector(&santa, Vec3::new(10.0, 0.0, 0.0))], "RIGHT");
+    }
+
+    #[test]
+    fn only_closing_missiles_have_an_impact_time() {
+        let offset = Vec3::new(0.0, 0.0, 100.0);
+        assert!((time_to_impact(offset, Vec3::new(0.0, 0.0, 50.0)) - 2.0).abs() < TOLERANCE);
+        // Only the closing part of the speed counts
+        assert!((time_to_impact(offset, Vec3::new(30.0, 0.0, 50.0)) - 2.0).abs() < TOLERANCE);
+        assert_eq!(time_to_impact(offset, Vec3::new(0.0, 0.0, -50.0)), f32::INFINITY);
+        assert_eq!(time_to_impact(offset, Vec3::new(50.0, 0.0, 0.0)), f32::INFINITY);
+        assert_eq!(time_to_impact(offset, Vec3::ZERO), f32::INFINITY);
+    }
+}
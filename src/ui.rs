@@ -4,10 +4,13 @@ use belly::prelude::*;
 use bevy::prelude::*;
 use bevy::app::{App, Plugin, Startup};
 use bevy::prelude::{Commands, Entity, Event, EventReader};
-use crate::camera::GameCamera;
+use crate::camera::{CameraMode, CameraRig, GameCamera};
 use crate::sam_site::SamSite;
 use crate::santa::{GameEvent, GameEventTypes, Santa, SantaStats, TargetEvent, TargetEventTypes};
-use crate::villages::{House, LoadLevel};
+use crate::collisions::VillageFinished;
+use crate::menus::MenuState;
+use crate::villages::{Disposition, GameTracker, GiftDemand, House, LoadLevel, NeedsGifts, VillageCenter};
+use crate::constants::WORLD_BAR_RANGE;
 
 pub struct UiPlugin;
 
@@ -15,6 +18,7 @@ impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_plugins(BellyPlugin)
+            .add_event::<AddHealthBar>()
             .insert_resource(UiResources {
                 target_color: Color::RED,
             })
@@ -30,6 +34,10 @@ impl Plugin for UiPlugin {
                     target_indicator_system,
                     fellow_system,
                     game_over_handler,
+                    village_list_system,
+                    request_world_bars,
+                    add_world_bars,
+                    world_bar_system,
                 ))
         ;
     }
@@ -41,6 +49,13 @@ pub struct UiResources {
     pub target_color: Color,
 }
 
+/// Only touch a bound field when the value really changed, belly rebuilds the text otherwise
+pub fn set_if_changed<T: PartialEq>(field: &mut T, value: T) {
+    if *field != value {
+        *field = value;
+    }
+}
+
 pub fn spawn_ui(mut commands: Commands) {
     commands.add(ess! {
         body {
@@ -101,10 +116,34 @@ pub fn spawn_ui(mut commands: Commands) {
         //     row-gap: 10px;
         //     height: 5%;
         // }
+        .village_list {
+            display: flex;
+            flex-direction: column;
+            font-size: 16px;
+            color: white;
+        }
+        .village_row {
+            column-gap: 12px;
+        }
         .text-header {
             font: bold;
             font-size: 24px;
         }
+        .world_bar {
+            flex-direction: column;
+            align-items: center;
+            font-size: 10px;
+            color: white;
+        }
+        .world_bar_track {
+            width: 48px;
+            height: 5px;
+            background-color: #000000aa;
+        }
+        .world_bar_fill {
+            height: 100%;
+            background-color: #33dd33;
+        }
         .footer {
             font: bold;
             font-size: 24px;
@@ -121,7 +160,9 @@ pub fn spawn_ui(mut commands: Commands) {
     });
     commands.add(eml! {
         <body>
-            <span c:header></span>
+            <span c:header>
+                <span c:village_list id="ui-villages"></span>
+            </span>
             <span c:main>
             </span>
             <span c:footer id="ui-footer">
@@ -139,18 +180,100 @@ pub struct AddHealthBar {
     pub name: &'static str,
 }
 
+/// Anything that can fill a world bar, 0.0 is empty and 1.0 is full
+pub trait BarValue {
+    fn bar_value(&self) -> f32;
+}
+
+impl BarValue for SantaStats {
+    fn bar_value(&self) -> f32 {
+        self.health as f32 / 100.0
+    }
+}
+
+impl BarValue for GiftDemand {
+    fn bar_value(&self) -> f32 {
+        self.received as f32 / self.required.max(1) as f32
+    }
+}
+
+/// Reloading, full when it is about to fire again
+impl BarValue for SamSite {
+    fn bar_value(&self) -> f32 {
+        1.0 - self.time_left * self.rate_of_fire_per_minute / 60.0
+    }
+}
+
+/// Bars for SAM sites, houses that want more than one gift and Santa himself
+pub fn request_world_bars(
+    mut health_bar_ew: EventWriter<AddHealthBar>,
+    new_santas: Query<Entity, Added<SantaStats>>,
+    new_sam_sites: Query<Entity, Added<SamSite>>,
+    new_houses: Query<(Entity, &GiftDemand), Added<GiftDemand>>,
+) {
+    for entity in new_santas.iter() {
+        health_bar_ew.send(AddHealthBar { entity, name: "Santa" });
+    }
+    for entity in new_sam_sites.iter() {
+        health_bar_ew.send(AddHealthBar { entity, name: "SAM" });
+    }
+    for (entity, gift_demand) in new_houses.iter() {
+        if gift_demand.required > 1 {
+            health_bar_ew.send(AddHealthBar { entity, name: "Gifts" });
+        }
+    }
+}
+
+pub fn add_world_bars(
+    mut commands: Commands,
+    mut elements: Elements,
+    mut health_bar_er: EventReader<AddHealthBar>,
+) {
+    for add_health_bar in health_bar_er.read() {
+        let target = add_health_bar.entity;
+        let name = add_health_bar.name.to_string();
+        let fill = commands.spawn(WorldBarFill).id();
+        elements.select("body").add_child(eml! {
+            <world_bar target=target fill=fill c:world_bar>
+                <label value=name/>
+                <span c:world_bar_track>
+                    <span {fill} c:world_bar_fill s:width=managed()/>
+                </span>
+            </world_bar>
+        });
+    }
+}
+
 pub fn target_indicator_system(
     mut elements: Elements,
     mut target_er: EventReader<TargetEvent>,
+    house_query: Query<(&Disposition, Option<&GiftDemand>)>,
 ) {
     for target_aqcuired in &mut target_er.read() {
         match target_aqcuired.0 {
             TargetEventTypes::Acquired(house) => {
-                elements.select("body").add_child(eml! {
-                <fellow target=house c:target_indicator>
-                    <span c:target_child><label s:color="#ff0000" value="TARGET"/></span>
-                </fellow>
-        });
+                match house_query.get(house) {
+                    Ok((Disposition::Naughty, _)) => {
+                        elements.select("body").add_child(eml! {
+                        <fellow target=house c:target_indicator>
+                            <span c:target_child><label s:color="#222222" value="COAL"/></span>
+                        </fellow>
+                });
+                    }
+                    Ok((_, Some(gift_demand))) => {
+                        let required_label = format!("/{}", gift_demand.required);
+                        elements.select("body").add_child(eml! {
+                        <fellow target=house c:target_indicator>
+                            <span c:target_child>
+                                <label s:color="#ff0000" value="TARGET "/>
+                                <label s:color="#ff0000" bind:value=from!(house, GiftDemand:received | fmt.c("{c}") )/>
+                                <label s:color="#ff0000" value=required_label/>
+                            </span>
+                        </fellow>
+                });
+                    }
+                    _ => {}
+                }
             }
             TargetEventTypes::Lost => {
                 elements.select(".target_indicator").remove();
@@ -160,6 +283,35 @@ pub fn target_indicator_system(
     }
 }
 
+/// Rebuilds the list of villages that still need gifts whenever a village
+/// is spawned, finished or removed.
+pub fn village_list_system(
+    mut elements: Elements,
+    added_villages: Query<(), Added<VillageCenter>>,
+    mut removed_villages: RemovedComponents<VillageCenter>,
+    mut village_finished_er: EventReader<VillageFinished>,
+    village_query: Query<(Entity, &VillageCenter)>,
+) {
+    let village_finished = village_finished_er.read().count() > 0;
+    let village_removed = removed_villages.read().count() > 0;
+    if added_villages.is_empty() && !village_finished && !village_removed {
+        return;
+    }
+    elements.select(".village_row").remove();
+    for (village_entity, village) in village_query.iter() {
+        if !village.needs_gifts {
+            continue;
+        }
+        let village_name = if village.is_bonus { format!("{} (bonus)", village.name) } else { village.name.clone() };
+        elements.select("#ui-villages").add_child(eml! {
+            <span c:village_row>
+                <label value=village_name/>
+                <label bind:value=from!(village_entity, VillageCenter:needs_gifts_count | fmt.c("{c} houses left") )/>
+            </span>
+        });
+    }
+}
+
 #[derive(Resource)]
 pub struct SillyGameState {
     pub waiting_for_restart: bool,
@@ -171,9 +323,12 @@ pub fn game_over_handler(
     mut commands: Commands,
     sam_query: Query<Entity, With<SamSite>>,
     house_query: Query<Entity, With<House>>,
+    village_query: Query<Entity, With<VillageCenter>>,
     mut load_level_ew: EventWriter<LoadLevel>,
     santa_query: Query<Entity, With<Santa>>,
-    mut silly_game_state: ResMut<SillyGameState>
+    mut silly_game_state: ResMut<SillyGameState>,
+    mut game_tracker: ResMut<GameTracker>,
+    menu: Res<MenuState>,
 ) {
     for game_event in game_event.read() {
         let mut restart = false;
@@ -186,6 +341,14 @@ pub fn game_over_handler(
                 });
                 restart = true;
             }
+            GameEventTypes::OutOfTime => {
+                elements.select(".main").add_child(eml! {
+                    <div c:game_over_text>
+                        <span s:color="#ff0000" value="THE SUN IS UP AND CHRISTMAS IS RUINED! PRESS SPACE TO RESTART!"/>
+                    </div>
+                });
+                restart = true;
+            }
             GameEventTypes::Won => {
                 elements.select(".main").add_child(eml! {
                     <div c:game_over_text>
@@ -195,23 +358,27 @@ pub fn game_over_handler(
                 restart = true;
             }
             GameEventTypes::Started => {
-                load_level_ew.send(LoadLevel(1));
+                load_level_ew.send(LoadLevel(menu.start_level));
                 silly_game_state.waiting_for_restart = false;
                 let p = santa_query.get_single().unwrap();
                 elements.select("#ui-footer")
                     .add_child(eml! {
                         <span c:cell>
                             <label bind:value=from!(p, SantaStats:current_level | fmt.c("Current Level: {c}") )/>
-                            <label bind:value=from!(p, SantaStats:health | fmt.c("Health: {c}") )/>
                             <label bind:value=from!(p, SantaStats:houses_left | fmt.c("Houses Left: {c}") )/>
                             <label bind:value=from!(p, SantaStats:sam_sites | fmt.c("Sam Sites: {c}") )/>
+                            <label bind:value=from!(p, SantaStats:score | fmt.c("Score: {c}") )/>
+                            <label bind:value=from!(p, SantaStats:christmas_spirit | fmt.c("Christmas Spirit: {c}") )/>
+                            <label bind:value=from!(p, SantaStats:clock | fmt.c("Clock: {c}") )/>
+                            <label bind:value=from!(p, SantaStats:level_time_left | fmt.c("Time Left: {c}") )/>
                         </span>
                     });
             }
             GameEventTypes::Restarted => {
                 silly_game_state.waiting_for_restart = false;
+                game_tracker.score = 0;
                 elements.select(".game_over_text").remove();
-                load_level_ew.send(LoadLevel(1));
+                load_level_ew.send(LoadLevel(menu.start_level));
             }
         }
         if restart {
@@ -222,6 +389,9 @@ pub fn game_over_handler(
             for house in house_query.iter() {
                 commands.entity(house).despawn_recursive();
             }
+            for village in village_query.iter() {
+                commands.entity(village).despawn_recursive();
+            }
         }
     }
 }
@@ -270,4 +440,83 @@ pub fn fellow_system(
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// A bar floating above something in the world, laid out like `Fellow`
+#[derive(Component)]
+pub struct WorldBar {
+    pub target: Entity,
+    pub fill: Entity,
+}
+
+#[derive(Component)]
+pub struct WorldBarFill;
+
+#[widget]
+#[param(target: Entity => WorldBar: target)]
+#[param(fill: Entity => WorldBar: fill)]
+fn world_bar(ctx: &mut WidgetContext) {
+    let content = ctx.content();
+    ctx.render(eml! {
+        <span s:left=managed() s:top=managed() s:display=managed() s:position-type="absolute">
+            {content}
+        </span>
+    })
+}
+
+impl FromWorldAndParams for WorldBar {
+    fn from_world_and_params(_: &mut World, params: &mut Params) -> Self {
+        WorldBar {
+            target: params.try_get("target").expect("Missing required `target` param"),
+            fill: params.try_get("fill").expect("Missing required `fill` param"),
+        }
+    }
+}
+
+pub fn world_bar_system(
+    mut bars: Query<(Entity, &WorldBar, &mut Style, &Node), Without<WorldBarFill>>,
+    mut fills: Query<&mut Style, With<WorldBarFill>>,
+    transforms: Query<&GlobalTransform>,
+    santa_query: Query<&SantaStats>,
+    santa_transform_query: Query<&GlobalTransform, With<Santa>>,
+    house_query: Query<&GiftDemand, With<NeedsGifts>>,
+    sam_site_query: Query<&SamSite>,
+    mut commands: Commands,
+    camera_q: Query<(&Camera, &GlobalTransform, &CameraRig), With<GameCamera>>,
+) {
+    let Ok((camera, camera_global_transform, camera_rig)) = camera_q.get_single() else {
+        return;
+    };
+    let Ok(santa_transform) = santa_transform_query.get_single() else {
+        return;
+    };
+    for (entity, bar, mut style, node) in bars.iter_mut() {
+        let Ok(tr) = transforms.get(bar.target) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let value = if let Ok(santa_stats) = santa_query.get(bar.target) {
+            // No point in a bar over your own head in the cockpit
+            (camera_rig.mode != CameraMode::Cockpit).then(|| santa_stats.bar_value())
+        } else if tr.translation().distance(santa_transform.translation()) > WORLD_BAR_RANGE {
+            // Far away bars are only clutter
+            None
+        } else if let Ok(gift_demand) = house_query.get(bar.target) {
+            // Once the house has all it wanted the bar goes
+            (!gift_demand.is_met()).then(|| gift_demand.bar_value())
+        } else {
+            sam_site_query.get(bar.target).ok().map(|sam_site| sam_site.bar_value())
+        };
+        let position = camera.world_to_viewport(camera_global_transform, tr.translation() + Vec3::Y * 4.0);
+        let (Some(value), Some(pos)) = (value, position) else {
+            style.display = Display::None;
+            continue;
+        };
+        style.display = Display::Flex;
+        style.left = Val::Px((pos.x - 0.5 * node.size().x).round());
+        style.top = Val::Px((pos.y - node.size().y).round());
+        if let Ok(mut fill_style) = fills.get_mut(bar.fill) {
+            fill_style.width = Val::Percent(100.0 * value.clamp(0.0, 1.0));
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::f32::consts::PI;
+use bevy::math::{Quat, Vec3};
+use bevy_turborand::DelegatedRng;
+use crate::constants::{HOUSE_MIN_SPACING, SAM_HOUSE_SPACING, SAM_MIN_SPACING, STREET_WIDTH, TREE_CLEARANCE};
+
+/// How many darts we throw before growing the area we are scattering in
+const SCATTER_ATTEMPTS: u32 = 30;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VillageLayout {
+    StreetGrid,
+    PoissonScatter,
+    Ring,
+}
+
+impl VillageLayout {
+    pub fn random(rng: &mut impl DelegatedRng) -> Self {
+        match rng.u32(0..3) {
+            0 => VillageLayout::StreetGrid,
+            1 => VillageLayout::PoissonScatter,
+            _ => VillageLayout::Ring,
+        }
+    }
+}
+
+pub struct HousePlot {
+    /// Position relative to the christmas tree in the village center
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+pub struct Street {
+    pub from: Vec3,
+    pub to: Vec3,
+}
+
+/// Where everything in a village goes, relative to the christmas tree.
+pub struct VillagePlan {
+    pub houses: Vec<HousePlot>,
+    pub sam_sites: Vec<Vec3>,
+    pub streets: Vec<Street>,
+}
+
+impl VillagePlan {
+    /// Distance from the tree to whatever sits farthest out
+    pub fn radius(&self) -> f32 {
+        self.houses
+            .iter()
+            .map(|house| house.position)
+            .chain(self.sam_sites.iter().copied())
+            .chain(self.streets.iter().flat_map(|street| [street.from, street.to]))
+            .map(|position| position.length())
+            .fold(TREE_CLEARANCE, f32::max)
+    }
+}
+
+/// Lays out a village so that no two houses are closer than `HOUSE_MIN_SPACING`,
+/// no SAM site is closer than `SAM_HOUSE_SPACING` to a house or `SAM_MIN_SPACING`
+/// to another SAM site, and nothing is placed within `TREE_CLEARANCE` of the tree.
+pub fn plan_village(
+    layout: VillageLayout,
+    number_of_houses: usize,
+    number_of_sam_sites: usize,
+    rng: &mut impl DelegatedRng,
+) -> VillagePlan {
+    let (houses, streets) = match layout {
+        VillageLayout::StreetGrid => street_grid(number_of_houses),
+        VillageLayout::PoissonScatter => (poisson_scatter(number_of_houses, rng), vec![]),
+        VillageLayout::Ring => (ring(number_of_houses), vec![]),
+    };
+    let house_positions: Vec<Vec3> = houses.iter().map(|house| house.position).collect();
+    let sam_sites = scatter_sam_sites(&house_positions, number_of_sam_sites, rng);
+
+    VillagePlan {
+        houses,
+        sam_sites,
+        streets,
+    }
+}
+
+/// Rows of houses facing each other across streets running along the x axis.
+fn street_grid(number_of_houses: usize) -> (Vec<HousePlot>, Vec<Street>) {
+    if number_of_houses == 0 {
+        return (vec![], vec![]);
+    }
+    let columns = ((number_of_houses + 1) as f32).sqrt().ceil() as usize;
+    let mut rows = (number_of_houses + 1).div_ceil(columns).max(2);
+
+    loop {
+        // Two rows share a street, back to back rows are one plot apart
+        let row_z = |row: usize| {
+            (row / 2) as f32 * (2.0 * HOUSE_MIN_SPACING + STREET_WIDTH)
+                + (row % 2) as f32 * (HOUSE_MIN_SPACING + STREET_WIDTH)
+        };
+        let offset_x = (columns - 1) as f32 * HOUSE_MIN_SPACING / 2.0;
+        let offset_z = row_z(rows - 1) / 2.0;
+
+        let mut plots: Vec<HousePlot> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (row, column)))
+            .map(|(row, column)| HousePlot {
+                position: Vec3::new(
+                    column as f32 * HOUSE_MIN_SPACING - offset_x,
+                    0.0,
+                    row_z(row) - offset_z),
+                // Every house faces the street it is on
+                rotation: if row % 2 == 0 { Quat::IDENTITY } else { Quat::from_rotation_y(PI) },
+            })
+            .filter(|plot| plot.position.length() >= TREE_CLEARANCE)
+            .collect();
+
+        if plots.len() >= number_of_houses {
+            plots.sort_by(|a, b| a.position.length().total_cmp(&b.position.length()));
+            plots.truncate(number_of_houses);
+
+            let streets = (0..rows.div_ceil(2))
+                .filter(|block| block * 2 + 1 < rows)
+                .map(|block| {
+                    let z = row_z(block * 2) + (HOUSE_MIN_SPACING + STREET_WIDTH) / 2.0 - offset_z;
+                    Street {
+                        from: Vec3::new(-offset_x - HOUSE_MIN_SPACING / 2.0, 0.0, z),
+                        to: Vec3::new(offset_x + HOUSE_MIN_SPACING / 2.0, 0.0, z),
+                    }
+                })
+                .collect();
+            return (plots, streets);
+        }
+        rows += 1;
+    }
+}
+
+/// Houses evenly spread on a circle around the tree, all facing it.
+fn ring(number_of_houses: usize) -> Vec<HousePlot> {
+    if number_of_houses == 0 {
+        return vec![];
+    }
+    // The chord between two neighbours must be at least the house spacing
+    let chord_radius = if number_of_houses > 1 {
+        HOUSE_MIN_SPACING / (2.0 * (PI / number_of_houses as f32).sin())
+    } else {
+        0.0
+    };
+    let radius = chord_radius.max(TREE_CLEARANCE + HOUSE_MIN_SPACING / 2.0);
+
+    (0..number_of_houses)
+        .map(|n| {
+            let angle = n as f32 * 2.0 * PI / number_of_houses as f32;
+            HousePlot {
+                position: Vec3::new(angle.cos() * radius, 0.0, angle.sin() * radius),
+                rotation: Quat::from_rotation_y(PI / 2.0 - angle),
+            }
+        })
+        .collect()
+}
+
+/// Dart throwing Poisson-disk scatter, the area grows whenever it gets too crowded.
+fn poisson_scatter(number_of_houses: usize, rng: &mut impl DelegatedRng) -> Vec<HousePlot> {
+    let outer_radius = TREE_CLEARANCE + HOUSE_MIN_SPACING * (number_of_houses as f32).sqrt();
+    scatter(
+        &[],
+        0.0,
+        HOUSE_MIN_SPACING,
+        TREE_CLEARANCE,
+        outer_radius,
+        number_of_houses,
+        rng)
+        .into_iter()
+        .map(|position| HousePlot {
+            position,
+            rotation: Quat::from_rotation_y(rng.f32() * 2.0 * PI),
+        })
+        .collect()
+}
+
+fn scatter_sam_sites(houses: &[Vec3], number_of_sam_sites: usize, rng: &mut impl DelegatedRng) -> Vec<Vec3> {
+    let village_radius = houses
+        .iter()
+        .map(|house| house.length())
+        .fold(TREE_CLEARANCE, f32::max);
+    scatter(
+        houses,
+        SAM_HOUSE_SPACING,
+        SAM_MIN_SPACING,
+        TREE_CLEARANCE,
+        village_radius + SAM_HOUSE_SPACING,
+        number_of_sam_sites,
+        rng)
+}
+
+/// Throws `count` points into the annulus between `inner_radius` and `outer_radius`,
+/// keeping `spacing_to_existing` from `existing` and `spacing` between each other.
+fn scatter(
+    existing: &[Vec3],
+    spacing_to_existing: f32,
+    spacing: f32,
+    inner_radius: f32,
+    mut outer_radius: f32,
+    count: usize,
+    rng: &mut impl DelegatedRng,
+) -> Vec<Vec3> {
+    let mut points: Vec<Vec3> = Vec::with_capacity(count);
+    while points.len() < count {
+        let mut placed = false;
+        for _ in 0..SCATTER_ATTEMPTS {
+            let angle = rng.f32() * 2.0 * PI;
+            // Uniform over the area of the annulus, not the radius
+            let radius = (inner_radius * inner_radius
+                + rng.f32() * (outer_radius * outer_radius - inner_radius * inner_radius))
+                .sqrt();
+            let candidate = Vec3::new(angle.cos() * radius, 0.0, angle.sin() * radius);
+
+            if existing.iter().all(|point| point.distance(candidate) >= spacing_to_existing)
+                && points.iter().all(|point| point.distance(candidate) >= spacing) {
+                points.push(candidate);
+                placed = true;
+                break;
+            }
+        }
+        if !placed {
+            outer_radius += spacing.max(spacing_to_existing) / 2.0;
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_turborand::GlobalRng;
+    use super::*;
+
+    /// Points on a circle don't land exactly on the radius they were put on
+    const TOLERANCE: f32 = 0.001;
+    const LAYOUTS: [VillageLayout; 3] = [VillageLayout::StreetGrid, VillageLayout::PoissonScatter, VillageLayout::Ring];
+
+    fn plans() -> impl Iterator<Item=(VillageLayout, usize, usize, VillagePlan)> {
+        LAYOUTS.into_iter().flat_map(|layout| {
+            [(0, 0), (1, 0), (2, 1), (3, 2), (5, 4), (12, 8), (30, 99)]
+                .into_iter()
+                .flat_map(move |(houses, sam_sites)| {
+                    (0..5).map(move |seed| {
+                        let mut rng = GlobalRng::with_seed(seed);
+                        (layout, houses, sam_sites, plan_village(layout, houses, sam_sites, &mut rng))
+                    })
+                })
+        })
+    }
+
+    #[test]
+    fn places_the_requested_number_of_houses_and_sam_sites() {
+        for (layout, houses, sam_sites, plan) in plans() {
+            assert_eq!(plan.houses.len(), houses, "{:?}", layout);
+            assert_eq!(plan.sam_sites.len(), sam_sites, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn houses_keep_their_distance() {
+        for (layout, _, _, plan) in plans() {
+            for (i, a) in plan.houses.iter().enumerate() {
+                for b in plan.houses.iter().skip(i + 1) {
+                    assert!(a.position.distance(b.position) >= HOUSE_MIN_SPACING - TOLERANCE,
+                            "{:?}: houses at {} and {} overlap", layout, a.position, b.position);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sam_sites_keep_their_distance() {
+        for (layout, _, _, plan) in plans() {
+            for (i, sam_site) in plan.sam_sites.iter().enumerate() {
+                for house in plan.houses.iter() {
+                    assert!(sam_site.distance(house.position) >= SAM_HOUSE_SPACING - TOLERANCE,
+                            "{:?}: SAM site at {} is on top of house at {}", layout, sam_site, house.position);
+                }
+                for other in plan.sam_sites.iter().skip(i + 1) {
+                    assert!(sam_site.distance(*other) >= SAM_MIN_SPACING - TOLERANCE,
+                            "{:?}: SAM sites at {} and {} overlap", layout, sam_site, other);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_is_placed_on_the_christmas_tree() {
+        for (layout, _, _, plan) in plans() {
+            for house in plan.houses.iter() {
+                assert!(house.position.length() >= TREE_CLEARANCE - TOLERANCE, "{:?}: house at {}", layout, house.position);
+            }
+            for sam_site in plan.sam_sites.iter() {
+                assert!(sam_site.length() >= TREE_CLEARANCE - TOLERANCE, "{:?}: SAM site at {}", layout, sam_site);
+            }
+        }
+    }
+}
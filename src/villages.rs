@@ -1,18 +1,23 @@
-use bevy::app::{App, Plugin, PostStartup, Startup, Update};
+use bevy::app::{App, Plugin, Startup, Update};
 use bevy::asset::{Assets, AssetServer, Handle};
 use bevy::core::Name;
 use bevy::hierarchy::BuildChildren;
-use bevy::math::{EulerRot, Quat, Vec3};
-use bevy::pbr::{PbrBundle, StandardMaterial};
-use bevy::prelude::{Color, Commands, Component, Entity, Event, EventReader, EventWriter, GlobalTransform, Mesh, Query, Res, ResMut, Resource, Scene, shape, Transform, With};
+use bevy::math::{EulerRot, Quat, Vec2, Vec3};
+use bevy::pbr::{PbrBundle, PointLight, PointLightBundle, StandardMaterial};
+use bevy::prelude::{Color, Commands, Component, Entity, Event, EventReader, EventWriter, GlobalTransform, Mesh, Query, Res, ResMut, Resource, Scene, shape, Transform, TransformBundle, Visibility, With};
 use bevy::scene::SceneBundle;
 use bevy::utils::default;
 use bevy_turborand::{DelegatedRng, GlobalRng};
 use bevy_xpbd_3d::components::{Collider, CollisionLayers, RigidBody};
 use bevy_xpbd_3d::math::PI;
-use crate::constants::{GROUND_PLANE, HOUSE_RADIUS, HOUSE_SPAWN_DISTANCE};
+use crate::constants::{EMPTY_HOUSE_CHANCE, HOUSE_RADIUS, HOUSE_SPAWN_DISTANCE, LAST_LEVEL, LEVEL_BASE_TIME_LIMIT, LEVEL_TIME_LIMIT_PER_LEVEL, NAUGHTY_BASE_CHANCE, NAUGHTY_CHANCE_PER_LEVEL, NAUGHTY_MAX_CHANCE, STREET_WIDTH, VILLAGE_FLAT_MARGIN};
+use crate::lights::DynamicLight;
+use crate::particles::{ParticleEffect, ParticleEmitter};
 use crate::sam_site::SpawnSamSiteAt;
-use crate::santa::{CollisionLayer, FixChildTransform, GameEvent, GameEventTypes, NeedsTransformFix, ParentEntity, Santa};
+use crate::terrain::{FlatSite, Terrain};
+use crate::weather::WeatherProfile;
+use crate::village_layout::{plan_village, VillageLayout};
+use crate::santa::{CollisionLayer, GameEvent, GameEventTypes, ParentEntity, Payload, Santa};
 
 pub struct VillagePlugin;
 
@@ -30,9 +35,6 @@ impl Plugin for VillagePlugin {
             .add_systems(Startup,
                          load_level_assets,
             )
-            .add_systems(PostStartup,
-                         create_ground,
-            )
             .add_systems(Update,
                          (
                              load_level,
@@ -49,7 +51,14 @@ pub struct LoadLevel(pub u32);
 pub struct HouseEvent(pub HouseEventType);
 
 pub enum HouseEventType {
-    ReceivedGifts(Entity),
+    /// A gift hit the house, it might want more than one
+    ReceivedGifts {
+        house: Entity,
+        gift: Entity,
+        payload: Payload,
+        zone: HitZoneType,
+        impact_point: Vec3,
+    },
 }
 
 #[derive(Resource)]
@@ -61,164 +70,445 @@ pub struct GameTracker {
 
 #[derive(Component)]
 pub struct VillageCenter {
+    pub name: String,
     pub level: u32,
     pub needs_gifts_count: i32,
     pub needs_gifts: bool,
+    /// Bonus villages are optional, the level is finished without them
+    pub is_bonus: bool,
 }
 
 
 #[derive(Component)]
 pub struct NeedsGifts;
 
+/// Naughty houses get coal, not presents
+#[derive(Component)]
+pub struct NeedsCoal;
+
+/// Santa's list, checked twice
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Disposition {
+    Nice,
+    Naughty,
+    /// Nobody home, nothing to deliver
+    Empty,
+}
+
+/// A naughty house that Rudolph has found with his nose
+#[derive(Component)]
+pub struct Revealed;
+
+/// Hidden above naughty houses until Rudolph's nose finds them
+#[derive(Component)]
+pub struct NaughtyMarker;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HouseType {
+    Small,
+    Town,
+    Large,
+}
+
 #[derive(Component)]
 pub struct House {
     pub belongs_to_village: Entity,
+    pub house_type: HouseType,
 }
 
 impl House {
-    pub fn new(belongs_to_village: Entity) -> Self {
+    pub fn new(belongs_to_village: Entity, house_type: HouseType) -> Self {
         Self {
             belongs_to_village,
+            house_type,
         }
     }
 }
 
-fn create_ground(
-    mut commands: Commands,
-    level_assets: Res<LevelAssets>,
-) {
-    commands.spawn((
-        Name::from("Ground"),
-        PbrBundle {
-            mesh: level_assets.ground_mesh.clone(),
-            material: level_assets.ground_material.clone(),
-            transform: Transform::from_xyz(0.0, GROUND_PLANE, 0.0),
-            ..Default::default()
-        },
-    ));
+/// How many gifts a house wants before it counts as done
+#[derive(Component)]
+pub struct GiftDemand {
+    pub required: u32,
+    pub received: u32,
+}
+
+impl GiftDemand {
+    pub fn for_house_type(house_type: HouseType) -> Self {
+        Self {
+            required: match house_type {
+                HouseType::Small => 1,
+                HouseType::Town => 2,
+                HouseType::Large => 3,
+            },
+            received: 0,
+        }
+    }
+
+    pub fn is_met(&self) -> bool {
+        self.received >= self.required
+    }
 }
 
 #[derive(Component)]
 pub struct HouseChild;
 
+/// Which part of a house a gift hit, the chimney is worth the most
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HitZoneType {
+    Wall,
+    Roof,
+    Chimney,
+}
+
+impl HitZoneType {
+    pub fn score(&self) -> u32 {
+        match self {
+            HitZoneType::Wall => 50,
+            HitZoneType::Roof => 100,
+            HitZoneType::Chimney => 300,
+        }
+    }
+
+    /// A gift touching several zones at once counts as the best one
+    pub fn precision(&self) -> u8 {
+        match self {
+            HitZoneType::Wall => 0,
+            HitZoneType::Roof => 1,
+            HitZoneType::Chimney => 2,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct HitZone {
+    pub zone_type: HitZoneType,
+    pub half_extents: Vec3,
+}
+
+impl HitZone {
+    /// The point on the zone box closest to `point`, i.e. where a ball centred there touches it
+    pub fn closest_point(&self, zone_transform: &GlobalTransform, point: Vec3) -> Vec3 {
+        let local = zone_transform.affine().inverse().transform_point3(point);
+        zone_transform.transform_point(local.clamp(-self.half_extents, self.half_extents))
+    }
+}
+
+/// Size and offset of the wall, roof and chimney boxes for each house model
+fn house_hit_zones(house_type: HouseType) -> [(HitZoneType, Vec3, Vec3); 3] {
+    match house_type {
+        HouseType::Small => [
+            (HitZoneType::Wall, Vec3::new(7.0, 4.0, 6.0), Vec3::new(0.0, 2.0, 0.0)),
+            (HitZoneType::Roof, Vec3::new(7.0, 2.5, 6.0), Vec3::new(0.0, 5.25, 0.0)),
+            (HitZoneType::Chimney, Vec3::new(1.5, 2.0, 1.5), Vec3::new(1.5, 6.5, 0.0)),
+        ],
+        HouseType::Town => [
+            (HitZoneType::Wall, Vec3::new(20.0, 7.0, 7.0), Vec3::new(0.0, 3.5, 0.0)),
+            (HitZoneType::Roof, Vec3::new(20.0, 4.5, 7.0), Vec3::new(0.0, 9.25, 0.0)),
+            (HitZoneType::Chimney, Vec3::new(2.0, 2.5, 2.0), Vec3::new(6.0, 12.0, 0.0)),
+        ],
+        HouseType::Large => [
+            (HitZoneType::Wall, Vec3::new(15.0, 9.0, 9.0), Vec3::new(0.0, 4.5, 0.0)),
+            (HitZoneType::Roof, Vec3::new(15.0, 3.5, 9.0), Vec3::new(0.0, 10.75, 0.0)),
+            (HitZoneType::Chimney, Vec3::new(2.5, 2.5, 2.5), Vec3::new(-4.0, 13.0, 0.0)),
+        ],
+    }
+}
+
+const VILLAGE_NAMES: [&str; 12] = [
+    "Holly Hollow",
+    "Mistletoe Mews",
+    "Snowdrift",
+    "Tinsel Town",
+    "Candy Cane Crossing",
+    "Frostbury",
+    "Ginger Glen",
+    "Jingle Heights",
+    "Yule Vale",
+    "Pinecone Point",
+    "Evergreen",
+    "Sleighbell Springs",
+];
+
+pub struct VillageDefinition {
+    pub number_of_houses: i32,
+    pub number_of_sam_sites: u32,
+    pub is_bonus: bool,
+}
+
+pub struct LevelDefinition {
+    pub villages: Vec<VillageDefinition>,
+    /// Seconds of the night Santa gets to deliver the level
+    pub time_limit: f32,
+    pub weather: WeatherProfile,
+}
+
+/// Splits `total` over `parts` so nothing is lost, the first ones get the remainder
+fn share(total: u32, parts: u32, index: u32) -> u32 {
+    total / parts + u32::from(index < total % parts)
+}
+
+impl LevelDefinition {
+    pub fn for_level(level: u32) -> Self {
+        let required_villages = 1 + level / 3;
+        let mut villages: Vec<VillageDefinition> = (0..required_villages)
+            .map(|index| VillageDefinition {
+                number_of_houses: share(level * 3, required_villages, index) as i32,
+                number_of_sam_sites: share(level * level - 1, required_villages, index),
+                is_bonus: false,
+            })
+            .collect();
+        if level % 2 == 0 {
+            villages.push(VillageDefinition {
+                number_of_houses: 2 + level as i32 / 2,
+                number_of_sam_sites: level,
+                is_bonus: true,
+            });
+        }
+        Self {
+            villages,
+            time_limit: LEVEL_BASE_TIME_LIMIT + level as f32 * LEVEL_TIME_LIMIT_PER_LEVEL,
+            weather: WeatherProfile::for_level(level),
+        }
+    }
+}
+
 fn load_level(
     mut commands: Commands,
     mut load_level_er: EventReader<LoadLevel>,
     level_assets: Res<LevelAssets>,
+    mut terrain: ResMut<Terrain>,
     mut global_rng: ResMut<GlobalRng>,
     santas_global_transform: Query<&GlobalTransform, With<Santa>>,
     mut spawn_sam_sites_ew: EventWriter<SpawnSamSiteAt>,
     mut game_won_ew: EventWriter<GameEvent>
 ) {
     for load_level in load_level_er.read() {
-        if load_level.0 > 10 {
+        if load_level.0 > LAST_LEVEL {
             game_won_ew.send(GameEvent{ event_type: GameEventTypes::Won });
             continue;
         }
-        let number_of_houses: i32 = (load_level.0 * 2 + load_level.0) as i32;
+        let level_definition = LevelDefinition::for_level(load_level.0);
+        // The old villages are gone, so is the ground they levelled
+        terrain.flat_sites.clear();
+        let santas_position = santas_global_transform.single();
+        let number_of_villages = level_definition.villages.len();
+        // No two villages in a level share a name
+        let mut village_names = VILLAGE_NAMES;
+        global_rng.shuffle(&mut village_names);
 
-        let number_of_sam_sites = load_level.0 * load_level.0 - 1;
+        for (index, village_definition) in level_definition.villages.iter().enumerate() {
+            // Spread the villages out around Santa so they don't end up on top of each other
+            let spread_angle = index as f32 * 2.0 * PI / number_of_villages as f32;
+            let village_direction = (if load_level.0 == 1 { Vec3::Z } else {  Quat::from_euler(EulerRot::YXZ, spread_angle + global_rng.f32_normalized() * PI / number_of_villages as f32, 0.0 ,0.0).mul_vec3(santas_position.forward()) }) * global_rng.u32(2..10) as f32 * HOUSE_SPAWN_DISTANCE;
 
-        let santas_position = santas_global_transform.single();
+            let village_center_position = Vec3::new(global_rng.i32(-HOUSE_RADIUS..=HOUSE_RADIUS) as f32 + village_direction.x, 0.0, global_rng.i32(-HOUSE_RADIUS..=HOUSE_RADIUS) as f32 + village_direction.z);
+            let village_name = village_names[index % village_names.len()];
 
-        let village_direction = (if load_level.0 == 1 { Vec3::Z } else {  Quat::from_euler(EulerRot::ZYX, global_rng.f32_normalized() * PI, 0.0 ,0.0).mul_vec3(santas_position.forward()) }) * global_rng.u32(2..10) as f32 * HOUSE_SPAWN_DISTANCE;
+            spawn_village(
+                &mut commands,
+                &level_assets,
+                &mut terrain,
+                &mut global_rng,
+                &mut spawn_sam_sites_ew,
+                load_level.0,
+                village_name,
+                village_center_position,
+                village_definition,
+            );
+        }
+    }
+}
 
-        let village_center_position = Vec3::new(global_rng.i32(-HOUSE_RADIUS..=HOUSE_RADIUS) as f32 + village_direction.x, GROUND_PLANE, global_rng.i32(-HOUSE_RADIUS..=HOUSE_RADIUS) as f32 + village_direction.z);
-        let village_entity = commands.spawn(
-            (
-                VillageCenter {
-                    level: load_level.0,
-                    needs_gifts_count: number_of_houses,
-                    needs_gifts: true,
-                },
-                SceneBundle {
-                    scene: level_assets.christmas_tree.clone(),
-                    transform: Transform::from_translation(village_center_position),
-                    ..default()
-                }
-        ))
-        .id();
-
-        let mut min_x = 0.0;
-        let mut max_x = 0.0;
-        let mut min_y  = 0.0;
-        let mut max_y = 0.0;
-
-        for n in 0..number_of_houses {
-            let house_type = global_rng.i32(0..3);
-            let house =
-                match house_type {
-                    0 => level_assets.house_small.clone(),
-                    1 => level_assets.house_town.clone(),
-                    2 => level_assets.house_large.clone(),
-                    _ => panic!("Invalid house type"),
-                };
-            let x_i: i32 = n % (number_of_houses / 2) - number_of_houses / 2;
-            let z_i: i32 = n / (number_of_houses / 2) - number_of_houses / 2;
-            let x = village_center_position.x + x_i as f32 * 30.0;
-            let z = village_center_position.z + z_i as f32 * 30.0;
-            let y = village_center_position.y;
-
-            if x < min_x {
-                min_x = x;
+#[allow(clippy::too_many_arguments)]
+fn spawn_village(
+    commands: &mut Commands,
+    level_assets: &LevelAssets,
+    terrain: &mut Terrain,
+    global_rng: &mut GlobalRng,
+    spawn_sam_sites_ew: &mut EventWriter<SpawnSamSiteAt>,
+    level: u32,
+    village_name: &str,
+    mut village_center_position: Vec3,
+    village_definition: &VillageDefinition,
+) -> Entity {
+    let number_of_houses = village_definition.number_of_houses;
+
+    let naughty_chance = (NAUGHTY_BASE_CHANCE + level as f32 * NAUGHTY_CHANCE_PER_LEVEL).min(NAUGHTY_MAX_CHANCE);
+    let mut dispositions: Vec<Disposition> = (0..number_of_houses)
+        .map(|_| {
+            let roll = global_rng.f32();
+            if roll < naughty_chance {
+                Disposition::Naughty
+            } else if roll < naughty_chance + EMPTY_HOUSE_CHANCE {
+                Disposition::Empty
+            } else {
+                Disposition::Nice
             }
-            if x > max_x {
-                max_x = x;
+        })
+        .collect();
+    // A village without a single nice house has nothing to deliver
+    if !dispositions.contains(&Disposition::Nice) {
+        if let Some(first) = dispositions.first_mut() {
+            *first = Disposition::Nice;
+        }
+    }
+    let nice_houses = dispositions.iter().filter(|disposition| **disposition == Disposition::Nice).count() as i32;
+
+    let plan = plan_village(
+        VillageLayout::random(global_rng),
+        number_of_houses as usize,
+        village_definition.number_of_sam_sites as usize,
+        global_rng);
+
+    // Level the ground first so no house ends up on a hill top in Santa's way
+    terrain.flat_sites.push(FlatSite {
+        center: Vec2::new(village_center_position.x, village_center_position.z),
+        radius: plan.radius() + VILLAGE_FLAT_MARGIN,
+    });
+    village_center_position.y = terrain.height_at(village_center_position.x, village_center_position.z);
+
+    let village_entity = commands.spawn(
+        (
+            Name::from(village_name.to_string()),
+            VillageCenter {
+                name: village_name.to_string(),
+                level,
+                needs_gifts_count: nice_houses,
+                needs_gifts: true,
+                is_bonus: village_definition.is_bonus,
+            },
+            SceneBundle {
+                scene: level_assets.christmas_tree.clone(),
+                transform: Transform::from_translation(village_center_position),
+                ..default()
             }
+    ))
+    .with_children(|children| {
+        // One warm glow over the whole village rather than a light in every house
+        children.spawn((
+            DynamicLight::new(1.0, false),
+            PointLightBundle {
+                point_light: PointLight {
+                    color: Color::rgb(1.0, 0.75, 0.4),
+                    intensity: 4000.0,
+                    range: plan.radius() + 15.0,
+                    radius: 0.0,
+                    shadows_enabled: false,
+                    ..default()
+                },
+                transform: Transform::from_xyz(0.0, 6.0, 0.0),
+                ..default()
+            },
+        ));
+    })
+    .id();
+
+    // Everything stands on the terrain rather than on the height of the tree
+    let on_the_ground = |local: Vec3| {
+        let world = village_center_position + local;
+        Vec3::new(world.x, terrain.height_at(world.x, world.z), world.z)
+    };
 
-            if y < min_y {
-                min_y = y;
+    for street in plan.streets.iter() {
+        let street_length = street.from.distance(street.to);
+        commands.spawn((
+            Name::from("Street"),
+            PbrBundle {
+                mesh: level_assets.street_mesh.clone(),
+                material: level_assets.street_material.clone(),
+                transform: Transform::from_translation(on_the_ground((street.from + street.to) / 2.0))
+                    .with_scale(Vec3::new(street_length, 1.0, STREET_WIDTH)),
+                ..default()
+            },
+        ));
+    }
+
+    for (house_plot, disposition) in plan.houses.iter().zip(dispositions) {
+        let house_type = match global_rng.i32(0..3) {
+            0 => HouseType::Small,
+            1 => HouseType::Town,
+            2 => HouseType::Large,
+            _ => panic!("Invalid house type"),
+        };
+        let house =
+            match house_type {
+                HouseType::Small => level_assets.house_small.clone(),
+                HouseType::Town => level_assets.house_town.clone(),
+                HouseType::Large => level_assets.house_large.clone(),
+            };
+
+        let zones = house_hit_zones(house_type);
+        let roof_top = zones
+            .iter()
+            .map(|(_, size, offset)| offset.y + size.y / 2.0)
+            .fold(0.0, f32::max);
+        let mut house_commands = commands.spawn(
+            (
+                SceneBundle {
+                    transform: Transform::from_translation(on_the_ground(house_plot.position))
+                        .with_rotation(house_plot.rotation),
+                    scene: house,
+                    ..Default::default()
+                },
+                RigidBody::Kinematic,
+                House::new(village_entity, house_type),
+                disposition,
+                CollisionLayers::new(
+                    [CollisionLayer::House],
+                    [
+                        CollisionLayer::Gift,
+                        CollisionLayer::Santa,
+                    ]),
+            ));
+        match disposition {
+            Disposition::Nice => {
+                house_commands.insert((GiftDemand::for_house_type(house_type), NeedsGifts));
             }
-            if y > max_y {
-                max_y = y;
+            Disposition::Naughty => {
+                house_commands.insert(NeedsCoal);
             }
-
-            commands.spawn(
-                (
-                    FixChildTransform::new(
-                        Vec3::new(0.0, 2.0, 0.0),
-                        Quat::from_euler(
-                            EulerRot::YXZ,
-                            0.0, 0.0, 0.0),
-                        Vec3::new(1.0, 1.0, 1.0),
-                    ),
-                    SceneBundle {
-                        transform: Transform::from_xyz(x, y, z),
-                        scene: house,
-                        ..Default::default()
-                    },
-                    RigidBody::Kinematic,
-                    House::new(village_entity),
-                    NeedsGifts,
-                    CollisionLayers::new(
-                        [CollisionLayer::House],
-                        [
-                            CollisionLayer::Gift,
-                            CollisionLayer::Santa,
-                        ]),
-                )).with_children(|children|
-                { // Spawn the child colliders positioned relative to the rigid body
-                    children.spawn(
+            Disposition::Empty => {}
+        }
+        house_commands.with_children(|children|
+            { // Spawn the child colliders positioned relative to the rigid body
+                for (zone_type, size, offset) in zones {
+                    let mut zone_commands = children.spawn(
                         (
                             ParentEntity(children.parent_entity()),
                             HouseChild,
-                            NeedsTransformFix,
-                            Collider::cuboid(10.0, 10.0, 10.0),
-                            Transform::from_xyz(0.0, 0.0, 0.0),
+                            HitZone {
+                                zone_type,
+                                half_extents: size / 2.0,
+                            },
+                            Collider::cuboid(size.x, size.y, size.z),
+                            TransformBundle::from_transform(Transform::from_translation(offset)),
                         ));
-                });
-        }
-        for _ in 0..number_of_sam_sites {
-
-            let x = global_rng.i32(min_x as i32..max_x as i32) as f32;
-            let y = global_rng.i32(min_y as i32..max_y as i32) as f32;
-
-            spawn_sam_sites_ew.send(SpawnSamSiteAt {
-                position: village_center_position + Vec3::new(x, 0.0, y),
-                belongs_to: village_entity
+                    // Only a house with somebody home has a fire going
+                    if zone_type == HitZoneType::Chimney && disposition != Disposition::Empty {
+                        zone_commands.insert(ParticleEmitter::new(ParticleEffect::ChimneySmoke));
+                    }
+                }
+                if disposition == Disposition::Naughty {
+                    children.spawn((
+                        NaughtyMarker,
+                        PbrBundle {
+                            mesh: level_assets.naughty_marker_mesh.clone(),
+                            material: level_assets.naughty_marker_material.clone(),
+                            transform: Transform::from_xyz(0.0, roof_top + 4.0, 0.0),
+                            visibility: Visibility::Hidden,
+                            ..default()
+                        },
+                    ));
+                }
             });
-        }
     }
+    for sam_site_position in plan.sam_sites.iter() {
+        spawn_sam_sites_ew.send(SpawnSamSiteAt {
+            position: on_the_ground(*sam_site_position),
+            belongs_to: village_entity
+        });
+    }
+    village_entity
 }
 
 #[derive(Resource, Default)]
@@ -226,8 +516,10 @@ pub struct LevelAssets {
     pub house_small: Handle<Scene>,
     pub house_town: Handle<Scene>,
     pub house_large: Handle<Scene>,
-    pub ground_mesh: Handle<Mesh>,
-    pub ground_material: Handle<StandardMaterial>,
+    pub street_mesh: Handle<Mesh>,
+    pub street_material: Handle<StandardMaterial>,
+    pub naughty_marker_mesh: Handle<Mesh>,
+    pub naughty_marker_material: Handle<StandardMaterial>,
     pub christmas_tree: Handle<Scene>,
 }
 
@@ -241,11 +533,90 @@ pub fn load_level_assets(
         house_small: asset_server.load("models/houses/house.glb#Scene0"),
         house_town: asset_server.load("models/houses/house-town.glb#Scene0"),
         house_large: asset_server.load("models/houses/house-large.glb#Scene0"),
-        ground_mesh: meshes.add(Mesh::from(shape::Plane { size: 100000.0, subdivisions: 4 })),
-        ground_material: materials.add(StandardMaterial {
-            base_color: Color::rgb(0.0, 0.1, 0.0),
+        street_mesh: meshes.add(Mesh::from(shape::Box::new(1.0, 0.2, 1.0))),
+        street_material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.15, 0.15, 0.18),
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+        naughty_marker_mesh: meshes.add(
+            shape::UVSphere {
+                radius: 1.5,
+                sectors: 6,
+                stacks: 4,
+            }.into()),
+        naughty_marker_material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.05, 0.05, 0.05),
+            emissive: Color::rgb(0.6, 0.0, 0.0),
             ..default()
         }),
         christmas_tree:asset_server.load("models/christmas-tree.glb#Scene0"),
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn share_never_loses_the_remainder() {
+        for total in 0..50 {
+            for parts in 1..8 {
+                let shares: Vec<u32> = (0..parts).map(|index| share(total, parts, index)).collect();
+                assert_eq!(shares.iter().sum::<u32>(), total, "{} over {}", total, parts);
+                let (min, max) = (shares.iter().min().unwrap(), shares.iter().max().unwrap());
+                assert!(max - min <= 1, "{} over {} is uneven: {:?}", total, parts, shares);
+            }
+        }
+    }
+
+    #[test]
+    fn levels_add_up_to_the_old_totals() {
+        for level in 1..=LAST_LEVEL {
+            let definition = LevelDefinition::for_level(level);
+            let (bonus, required): (Vec<&VillageDefinition>, Vec<&VillageDefinition>) =
+                definition.villages.iter().partition(|village| village.is_bonus);
+            assert_eq!(required.len() as u32, 1 + level / 3, "level {}", level);
+            assert_eq!(bonus.len(), usize::from(level % 2 == 0), "level {}", level);
+            assert_eq!(required.iter().map(|village| village.number_of_houses).sum::<i32>(), level as i32 * 3, "level {}", level);
+            assert_eq!(required.iter().map(|village| village.number_of_sam_sites).sum::<u32>(), level * level - 1, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn houses_are_done_once_they_got_what_they_asked_for() {
+        for (house_type, required) in [(HouseType::Small, 1), (HouseType::Town, 2), (HouseType::Large, 3)] {
+            let mut demand = GiftDemand::for_house_type(house_type);
+            for received in 0..required {
+                assert!(!demand.is_met(), "{:?} is done after {} gifts", house_type, received);
+                demand.received += 1;
+            }
+            assert!(demand.is_met(), "{:?} isn't done after {} gifts", house_type, required);
+            // A gift too many doesn't undo it
+            demand.received += 1;
+            assert!(demand.is_met(), "{:?}", house_type);
+        }
+    }
+
+    #[test]
+    fn closest_point_follows_the_rotation_of_the_zone() {
+        let zone = HitZone { zone_type: HitZoneType::Roof, half_extents: Vec3::new(2.0, 1.0, 1.0) };
+        let rotation = Quat::from_rotation_y(PI / 4.0);
+        let center = Vec3::new(10.0, 5.0, -3.0);
+        let zone_transform = GlobalTransform::from(Transform::from_translation(center).with_rotation(rotation));
+        let cases = [
+            // Inside the box the point is its own closest point
+            (Vec3::new(1.0, 0.5, -0.5), Vec3::new(1.0, 0.5, -0.5)),
+            // Straight out along the long side, which only lines up with x in the zone's own frame
+            (Vec3::new(5.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0)),
+            (Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 0.0, -1.0)),
+            // Off a corner
+            (Vec3::new(-3.0, 3.0, 3.0), Vec3::new(-2.0, 1.0, 1.0)),
+        ];
+        for (local_point, local_expected) in cases {
+            let point = center + rotation * local_point;
+            let expected = center + rotation * local_expected;
+            let closest = zone.closest_point(&zone_transform, point);
+            assert!(closest.distance(expected) < 0.001, "{}: expected {}, got {}", local_point, expected, closest);
+        }
+    }
+}
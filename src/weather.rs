@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+use bevy::app::{App, Plugin, Update};
+use bevy::math::{Quat, Vec3};
+use bevy::pbr::{FogFalloff, FogSettings};
+use bevy::prelude::{Component, EventReader, GlobalTransform, IntoSystemConfigs, Query, Res, ResMut, Resource, Time, With, Without};
+use bevy_turborand::{DelegatedRng, GlobalRng};
+use bevy_xpbd_3d::prelude::LinearVelocity;
+use crate::camera::GameCamera;
+use crate::constants::{BLIZZARD_VISIBILITY, GIFT_WIND_FACTOR, MISSILE_WIND_FACTOR, SANTA_WIND_FACTOR, STORM_DURATION, STORM_WIND_MULTIPLIER, WEATHER_CHANGE_RATE};
+use crate::input::{KinematicMovement, kinematic_movement};
+use crate::sam_site::{control_missiles, SamTarget, SurfaceToAirMissile};
+use crate::santa::{Payload, Santa};
+use crate::settings::Settings;
+use crate::villages::{LevelDefinition, LoadLevel};
+
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Weather::new(WeatherProfile::Clear))
+            .add_systems(
+                Update, (
+                    set_level_weather,
+                    change_weather,
+                    blow_santa.after(kinematic_movement),
+                    blow_missiles.after(control_missiles),
+                    weather_fog,
+                ))
+        ;
+    }
+}
+
+/// What kind of night a level gets, picked by the level definition.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WeatherProfile {
+    Clear,
+    Snowy,
+    Windy,
+    Blizzard,
+}
+
+impl WeatherProfile {
+    pub fn for_level(level: u32) -> Self {
+        match level {
+            1 => WeatherProfile::Clear,
+            l if l % 5 == 0 => WeatherProfile::Blizzard,
+            l if l % 3 == 0 => WeatherProfile::Windy,
+            _ => WeatherProfile::Snowy,
+        }
+    }
+
+    /// Average wind speed when there's no storm
+    pub fn wind_speed(&self) -> f32 {
+        match self {
+            WeatherProfile::Clear => 1.0,
+            WeatherProfile::Snowy => 3.0,
+            WeatherProfile::Windy => 10.0,
+            WeatherProfile::Blizzard => 8.0,
+        }
+    }
+
+    /// 0 is no snow at all, 1 is as much as we can draw
+    pub fn snowfall(&self) -> f32 {
+        match self {
+            WeatherProfile::Clear => 0.1,
+            WeatherProfile::Snowy => 0.6,
+            WeatherProfile::Windy => 0.3,
+            WeatherProfile::Blizzard => 0.8,
+        }
+    }
+
+    pub fn visibility(&self) -> f32 {
+        match self {
+            WeatherProfile::Clear => 1500.0,
+            WeatherProfile::Snowy => 900.0,
+            WeatherProfile::Windy => 1200.0,
+            WeatherProfile::Blizzard => 600.0,
+        }
+    }
+
+    /// Chance per second that a storm blows in
+    pub fn storm_chance(&self) -> f32 {
+        match self {
+            WeatherProfile::Clear => 0.0,
+            WeatherProfile::Snowy => 0.005,
+            WeatherProfile::Windy => 0.01,
+            WeatherProfile::Blizzard => 0.03,
+        }
+    }
+}
+
+/// The weather right now. Everything that feels the weather reads this instead
+/// of rolling its own random numbers.
+#[derive(Resource)]
+pub struct Weather {
+    pub profile: WeatherProfile,
+    /// Wind in world space, units per second
+    pub wind: Vec3,
+    pub snowfall: f32,
+    pub visibility: f32,
+    /// Seconds left of the current storm, 0 when there is none
+    pub storm_time_left: f32,
+    wind_angle: f32,
+    elapsed: f32,
+}
+
+impl Weather {
+    pub fn new(profile: WeatherProfile) -> Self {
+        Self {
+            profile,
+            wind: Vec3::ZERO,
+            snowfall: profile.snowfall(),
+            visibility: profile.visibility(),
+            storm_time_left: 0.0,
+            wind_angle: 0.0,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn is_storming(&self) -> bool {
+        self.storm_time_left > 0.0
+    }
+}
+
+fn set_level_weather(
+    mut weather: ResMut<Weather>,
+    mut load_level_er: EventReader<LoadLevel>,
+) {
+    for load_level in load_level_er.read() {
+        weather.profile = LevelDefinition::for_level(load_level.0).weather;
+        weather.storm_time_left = 0.0;
+    }
+}
+
+fn change_weather(
+    mut weather: ResMut<Weather>,
+    mut global_rng: ResMut<GlobalRng>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    let profile = weather.profile;
+    weather.elapsed += delta;
+
+    if weather.is_storming() {
+        weather.storm_time_left -= delta;
+    } else if global_rng.f32() < profile.storm_chance() * delta {
+        weather.storm_time_left = STORM_DURATION * (0.5 + global_rng.f32());
+    }
+
+    // The wind wanders around slowly and comes in gusts
+    weather.wind_angle += global_rng.f32_normalized() * delta * 0.2;
+    let gust = 1.0 + 0.5 * (weather.elapsed * 0.7).sin() * (weather.elapsed * 0.23).sin();
+    let (storm_wind, target_snowfall, target_visibility) = if weather.is_storming() {
+        (STORM_WIND_MULTIPLIER, 1.0, BLIZZARD_VISIBILITY)
+    } else {
+        (1.0, profile.snowfall(), profile.visibility())
+    };
+    let target_wind = Quat::from_rotation_y(weather.wind_angle % (2.0 * PI))
+        .mul_vec3(Vec3::X) * profile.wind_speed() * storm_wind * gust;
+
+    // Ease towards the new weather so a storm rolls in instead of switching on
+    let t = (WEATHER_CHANGE_RATE * delta).min(1.0);
+    weather.wind = weather.wind.lerp(target_wind, t);
+    weather.snowfall += (target_snowfall - weather.snowfall) * t;
+    weather.visibility += (target_visibility - weather.visibility) * t;
+}
+
+/// Movement sets the velocity from scratch every frame, so the wind is added on top afterwards
+fn blow_santa(
+    weather: Res<Weather>,
+    mut santa_query: Query<&mut LinearVelocity, (With<Santa>, With<KinematicMovement>)>,
+) {
+    for mut linear_velocity in santa_query.iter_mut() {
+        linear_velocity.0 += weather.wind * SANTA_WIND_FACTOR;
+    }
+}
+
+/// How much of a missile's velocity is the wind, so it can be swapped for this
+/// frame's wind instead of piling up
+#[derive(Component, Default)]
+pub struct WindDrift(pub Vec3);
+
+/// Gifts are light and drift a lot, SAMs are fast and barely notice
+fn blow_missiles(
+    weather: Res<Weather>,
+    mut missile_query: Query<(&mut LinearVelocity, &mut WindDrift, &SamTarget, Option<&Payload>), (With<SurfaceToAirMissile>, Without<Santa>)>,
+    target_query: Query<(), With<GlobalTransform>>,
+) {
+    for (mut linear_velocity, mut wind_drift, target, payload) in missile_query.iter_mut() {
+        let factor = if payload.is_some() { GIFT_WIND_FACTOR } else { MISSILE_WIND_FACTOR };
+        // Missiles that are still steering get a fresh velocity every frame,
+        // the ones that lost their target still carry last frame's drift
+        if !target_query.contains(target.0) {
+            linear_velocity.0 -= wind_drift.0;
+        }
+        wind_drift.0 = weather.wind * factor;
+        linear_velocity.0 += wind_drift.0;
+    }
+}
+
+fn weather_fog(
+    weather: Res<Weather>,
+    mut fog_query: Query<&mut FogSettings, With<GameCamera>>,
+    settings: Res<Settings>,
+) {
+    for mut fog in fog_query.iter_mut() {
+        fog.falloff = FogFalloff::from_visibility(weather.visibility.min(settings.fog_distance));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::App;
+    use crate::sam_site::SamTarget;
+    use super::*;
+
+    #[test]
+    fn every_level_gets_its_weather() {
+        use WeatherProfile::*;
+        let expected = [Clear, Snowy, Windy, Snowy, Blizzard, Windy, Snowy, Snowy, Windy, Blizzard];
+        for (level, profile) in (1..).zip(expected) {
+            assert_eq!(WeatherProfile::for_level(level), profile, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn wind_drift_does_not_pile_up() {
+        let wind = Vec3::new(4.0, 0.0, -2.0);
+        let mut weather = Weather::new(WeatherProfile::Windy);
+        weather.wind = wind;
+        let mut app = App::new();
+        app.insert_resource(weather).add_systems(Update, blow_missiles);
+        // Nobody steers a missile that lost its target, so its velocity is only ever touched by the wind
+        let gone = app.world.spawn_empty().id();
+        app.world.despawn(gone);
+        let velocity = Vec3::new(0.0, 0.0, 30.0);
+        let missile = app.world.spawn((
+            SurfaceToAirMissile::new(1.0, 30.0, 50.0),
+            SamTarget(gone),
+            LinearVelocity(velocity),
+            WindDrift::default(),
+        )).id();
+
+        for _ in 0..10 {
+            app.update();
+        }
+        let blown = app.world.get::<LinearVelocity>(missile).unwrap().0;
+        assert!(blown.distance(velocity + wind * MISSILE_WIND_FACTOR) < 0.001, "{}", blown);
+
+        app.world.resource_mut::<Weather>().wind = Vec3::ZERO;
+        app.update();
+        let calm = app.world.get::<LinearVelocity>(missile).unwrap().0;
+        assert!(calm.distance(velocity) < 0.001, "{}", calm);
+    }
+}